@@ -0,0 +1,274 @@
+use std::{
+    fs,
+    path::PathBuf,
+    time::{Duration, Instant, SystemTime},
+};
+
+use time::OffsetDateTime;
+
+use crate::{
+    animation::{Easing, parse_duration},
+    bluetooth::BluetoothConnection,
+    clock,
+    colors::kelvin_to_color,
+    config::DeviceConfig,
+    controller::{Color, Controller},
+    state::{Mode, State},
+};
+
+/// How often the running alarm updates the strip and checks for snooze/cancel.
+const TICK: Duration = Duration::from_secs(1);
+/// A status file not refreshed for this long belongs to an alarm that died.
+const STALE: Duration = Duration::from_secs(5);
+
+const DEEP_RED: Color = Color { r: 255, g: 0, b: 0 };
+
+pub struct Alarm {
+    pub target: OffsetDateTime,
+    pub ramp: Duration,
+    /// Color temperature reached at the target time.
+    pub kelvin: u32,
+    /// Time to stay at full brightness after the target, accepting snoozes.
+    pub hold: Duration,
+}
+
+pub enum Control {
+    Snooze(Duration),
+    Cancel,
+}
+
+fn status_path() -> Option<PathBuf> {
+    State::dir().map(|d| d.join("alarm"))
+}
+
+fn control_path() -> Option<PathBuf> {
+    State::dir().map(|d| d.join("alarm-control"))
+}
+
+/// Asks the running alarm to snooze or cancel.
+pub fn send(control: Control) -> Result<String, String> {
+    let status = status_path().ok_or("Alarm Error: No state directory available")?;
+    let alive = fs::metadata(&status)
+        .and_then(|m| m.modified())
+        .is_ok_and(|t| t.elapsed().unwrap_or_default() < STALE);
+    let target = fs::read_to_string(&status).ok().filter(|_| alive);
+    let Some(target) = target else {
+        return Err("Alarm Error: no alarm is running".to_string());
+    };
+    let path = control_path().ok_or("Alarm Error: No state directory available")?;
+    let text = match control {
+        Control::Snooze(duration) => format!("snooze {}ms", duration.as_millis()),
+        Control::Cancel => "cancel".to_string(),
+    };
+    fs::write(&path, text).map_err(|e| format!("Alarm Error: {}: {e}", path.display()))?;
+    Ok(target.trim().to_string())
+}
+
+/// Refreshes the status file to show the alarm is still running.
+fn touch_status() {
+    if let Some(status) = status_path()
+        && let Ok(file) = fs::File::options().append(true).open(status)
+    {
+        let _ = file.set_modified(SystemTime::now());
+    }
+}
+
+/// Consumes a pending snooze or cancel request, if any, and refreshes the
+/// status file.
+fn take_control() -> Option<Control> {
+    touch_status();
+    let path = control_path()?;
+    let text = fs::read_to_string(&path).ok()?;
+    let _ = fs::remove_file(&path);
+    match text.split_once(' ') {
+        Some(("snooze", duration)) => parse_duration(duration).ok().map(Control::Snooze),
+        _ if text.trim() == "cancel" => Some(Control::Cancel),
+        _ => None,
+    }
+}
+
+/// Sunrise color at `progress` (0.0-1.0) through the ramp: deep red at the
+/// lowest brightness, warming and brightening to `kelvin` white.
+pub fn sunrise(progress: f64, kelvin: u32) -> Color {
+    let color = DEEP_RED.lerp(kelvin_to_color(kelvin), progress.clamp(0.0, 1.0));
+    // Perceived brightness is far from linear, so stay dim for longer
+    let level = 1.0 + 254.0 * Easing::EaseIn.apply(progress);
+    color.scaled(level.round() as u8)
+}
+
+/// Waits for the ramp to begin, then runs it on `device` until the hold time
+/// ends or the alarm is cancelled, keeping the connection open throughout.
+pub async fn run(alarm: &Alarm, device: &DeviceConfig) -> Result<(), String> {
+    let status = status_path().ok_or("Alarm Error: No state directory available")?;
+    if let Some(dir) = status.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Alarm Error: {e}"))?;
+    }
+    fs::write(&status, clock::format(alarm.target))
+        .map_err(|e| format!("Alarm Error: {}: {e}", status.display()))?;
+    if let Some(path) = control_path() {
+        let _ = fs::remove_file(path);
+    }
+
+    let result = ring(alarm, device).await;
+    let _ = fs::remove_file(&status);
+    result
+}
+
+async fn ring(alarm: &Alarm, device: &DeviceConfig) -> Result<(), String> {
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut ticker = tokio::time::interval(TICK);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    let mut target = alarm.target;
+    println!(
+        "Alarm set for {}, ramping over {} minutes",
+        clock::format(target),
+        alarm.ramp.as_secs() / 60
+    );
+
+    // Sleep until the ramp starts; snoozing now postpones the alarm
+    loop {
+        let until_start = Duration::try_from(target - clock::now()).unwrap_or_default();
+        if until_start <= alarm.ramp {
+            break;
+        }
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut ctrl_c => return Ok(()),
+        }
+        match take_control() {
+            Some(Control::Cancel) => {
+                println!("Alarm cancelled");
+                return Ok(());
+            }
+            Some(Control::Snooze(duration)) => {
+                target += duration;
+                if let Some(status) = status_path() {
+                    let _ = fs::write(status, clock::format(target));
+                }
+                println!("Alarm moved to {}", clock::format(target));
+            }
+            None => {}
+        }
+    }
+
+    // Connecting can take a while; stay visible to snooze and cancel, which
+    // are picked up once the ramp starts
+    let connect =
+        BluetoothConnection::new(device.mac.clone(), crate::CHARACTERISTIC_UUID.to_string());
+    tokio::pin!(connect);
+    let bluetooth = loop {
+        tokio::select! {
+            connected = &mut connect => break connected?.calibrated(device.calibration),
+            _ = ticker.tick() => touch_status(),
+            _ = &mut ctrl_c => return Ok(()),
+        }
+    };
+    let result = ramp(alarm, target, &bluetooth).await;
+    bluetooth.bye().await?;
+    let last = result?;
+
+    let mut state = State::load()?;
+    let mut device_state = state.device(&device.mac);
+    device_state.power = last.is_some();
+    if let Some(color) = last {
+        device_state.color = color;
+        device_state.brightness = 255;
+        device_state.mode = Mode::Color;
+    }
+    state.set_device(&device.mac, device_state);
+    state.save()
+}
+
+/// Runs the ramp and hold, returning the last color shown, or `None` if the
+/// strip was left off by a snooze.
+async fn ramp(
+    alarm: &Alarm,
+    target: OffsetDateTime,
+    bluetooth: &BluetoothConnection,
+) -> Result<Option<Color>, String> {
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+    let mut ticker = tokio::time::interval(TICK);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
+
+    // Starting late picks the ramp up where it would have been by now
+    let remaining = Duration::try_from(target - clock::now()).unwrap_or_default();
+    let missed = alarm.ramp.saturating_sub(remaining);
+    let started = Instant::now();
+    let mut end = started + (alarm.ramp - missed) + alarm.hold;
+    let progress = || {
+        if alarm.ramp.is_zero() {
+            1.0
+        } else {
+            (missed + started.elapsed()).as_secs_f64() / alarm.ramp.as_secs_f64()
+        }
+    };
+
+    // Set the color before powering on so the previous one does not flash
+    let first = sunrise(progress(), alarm.kelvin);
+    bluetooth.write(Controller::color(first)).await?;
+    bluetooth.write(Controller::power(true)).await?;
+
+    let mut last = Some(first);
+    let mut snoozed_until: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut ctrl_c => break,
+        }
+        match take_control() {
+            Some(Control::Cancel) => {
+                println!("Alarm cancelled");
+                break;
+            }
+            Some(Control::Snooze(duration)) => {
+                println!("Snoozing for {} minutes", duration.as_secs() / 60);
+                bluetooth.write(Controller::power(false)).await?;
+                snoozed_until = Some(Instant::now() + duration);
+                end = end.max(Instant::now() + duration + alarm.hold);
+            }
+            None => {}
+        }
+        if Instant::now() >= end {
+            break;
+        }
+        if let Some(until) = snoozed_until {
+            if Instant::now() < until {
+                continue;
+            }
+            snoozed_until = None;
+            bluetooth.write(Controller::power(true)).await?;
+            last = None;
+        }
+
+        let color = sunrise(progress(), alarm.kelvin);
+        if last != Some(color) {
+            bluetooth.write(Controller::color(color)).await?;
+            last = Some(color);
+        }
+    }
+    Ok(if snoozed_until.is_some() { None } else { last })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sunrise_runs_from_dim_red_to_white() {
+        assert_eq!(sunrise(0.0, 3000), Color { r: 1, g: 0, b: 0 });
+        assert_eq!(sunrise(1.0, 3000), kelvin_to_color(3000));
+        assert_eq!(sunrise(1.0, 6500), kelvin_to_color(6500));
+        // Progress outside the ramp is clamped
+        assert_eq!(sunrise(-1.0, 3000), sunrise(0.0, 3000));
+        assert_eq!(sunrise(2.0, 3000), sunrise(1.0, 3000));
+
+        let brightness = |c: Color| c.r as u32 + c.g as u32 + c.b as u32;
+        let steps: Vec<u32> = (0..=10)
+            .map(|i| brightness(sunrise(i as f64 / 10.0, 3000)))
+            .collect();
+        assert!(steps.windows(2).all(|w| w[0] <= w[1]), "{steps:?}");
+    }
+}
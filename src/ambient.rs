@@ -0,0 +1,430 @@
+use std::{
+    fs,
+    io::{self, Read},
+    path::PathBuf,
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use clap::ValueEnum;
+use image::RgbImage;
+use tokio::{
+    sync::watch,
+    time::{self, MissedTickBehavior},
+};
+
+use crate::{
+    bluetooth::BluetoothConnection,
+    controller::{Color, Controller},
+};
+
+/// Pixels examined per frame at most; larger frames are sampled with a stride.
+const MAX_SAMPLES: u32 = 65_536;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Method {
+    /// Mean color of the whole region
+    Average,
+    /// Mean color of the region's border, for bias lighting
+    Edge,
+    /// Most common color in the region
+    Dominant,
+}
+
+/// Sub-rectangle of the frame, as fractions of its width and height.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub struct Region {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Region {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+/// Parses `X,Y,W,H` with each value a 0.0-1.0 fraction of the frame.
+pub fn parse_region(s: &str) -> Result<Region, String> {
+    let values: Vec<f64> = s
+        .split(',')
+        .map(|v| v.trim().parse::<f64>())
+        .collect::<Result<_, _>>()
+        .map_err(|_| format!("invalid region '{s}', expected X,Y,W,H"))?;
+    let [x, y, width, height] = values[..] else {
+        return Err(format!("invalid region '{s}', expected X,Y,W,H"));
+    };
+    if values.iter().any(|v| !(0.0..=1.0).contains(v)) || width == 0.0 || height == 0.0 {
+        return Err(format!("region '{s}' must use fractions between 0 and 1"));
+    }
+    // Allow for rounding in sums such as 0.7 + 0.3
+    if x + width > 1.0 + f64::EPSILON || y + height > 1.0 + f64::EPSILON {
+        return Err(format!("region '{s}' must lie within the frame"));
+    }
+    Ok(Region {
+        x,
+        y,
+        width,
+        height,
+    })
+}
+
+pub enum Source {
+    /// An image file, re-read whenever it changes on disk.
+    Image(PathBuf),
+    /// Raw RGB24 frames of a fixed size on stdin.
+    Stdin { width: u32, height: u32 },
+}
+
+pub struct Options {
+    pub method: Method,
+    pub region: Region,
+    /// Border thickness for the edge method, as a fraction of the region.
+    pub edge: f64,
+    /// Chroma multiplier; 1.0 leaves colors unchanged.
+    pub saturation: f64,
+    /// 0.0 follows the source immediately, values towards 1.0 fade slower.
+    pub smoothing: f64,
+    pub interval: Duration,
+}
+
+/// Computes the color of `image` according to `options`.
+pub fn sample(image: &RgbImage, options: &Options) -> Color {
+    let (w, h) = image.dimensions();
+    if w == 0 || h == 0 {
+        return Color::default();
+    }
+    let region = options.region;
+    // At least one pixel, even for regions thinner than one or at the far edge
+    let x0 = ((region.x * w as f64) as u32).min(w - 1);
+    let y0 = ((region.y * h as f64) as u32).min(h - 1);
+    let x1 = (((region.x + region.width) * w as f64) as u32)
+        .min(w)
+        .max(x0 + 1);
+    let y1 = (((region.y + region.height) * h as f64) as u32)
+        .min(h)
+        .max(y0 + 1);
+    let edge_x = ((x1 - x0) as f64 * options.edge).ceil() as u32;
+    let edge_y = ((y1 - y0) as f64 * options.edge).ceil() as u32;
+    let area = (x1 - x0) * (y1 - y0);
+    let step = ((area / MAX_SAMPLES) as f64).sqrt().ceil().max(1.0) as usize;
+
+    let mut sums = [0u64; 3];
+    let mut count = 0u64;
+    let mut buckets = vec![(0u32, [0u64; 3]); 4096];
+    for y in (y0..y1).step_by(step) {
+        for x in (x0..x1).step_by(step) {
+            if x >= w || y >= h {
+                continue;
+            }
+            if options.method == Method::Edge
+                && x >= x0 + edge_x
+                && x < x1.saturating_sub(edge_x)
+                && y >= y0 + edge_y
+                && y < y1.saturating_sub(edge_y)
+            {
+                continue;
+            }
+            let [r, g, b] = image.get_pixel(x, y).0;
+            if options.method == Method::Dominant {
+                // 4 bits per channel
+                let bucket = &mut buckets
+                    [((r as usize >> 4) << 8) | ((g as usize >> 4) << 4) | (b as usize >> 4)];
+                bucket.0 += 1;
+                bucket.1[0] += r as u64;
+                bucket.1[1] += g as u64;
+                bucket.1[2] += b as u64;
+            } else {
+                sums[0] += r as u64;
+                sums[1] += g as u64;
+                sums[2] += b as u64;
+                count += 1;
+            }
+        }
+    }
+    if options.method == Method::Dominant
+        && let Some((n, bucket_sums)) = buckets.into_iter().max_by_key(|(n, _)| *n)
+    {
+        count = n as u64;
+        sums = bucket_sums;
+    }
+    if count == 0 {
+        return Color::default();
+    }
+
+    let color = Color {
+        r: (sums[0] / count) as u8,
+        g: (sums[1] / count) as u8,
+        b: (sums[2] / count) as u8,
+    };
+    let [l, a, b] = color.to_oklab();
+    Color::from_oklab([l, a * options.saturation, b * options.saturation])
+}
+
+enum Frame {
+    New(RgbImage),
+    Unchanged,
+    Ended,
+}
+
+enum Frames {
+    File {
+        path: PathBuf,
+        modified: Option<SystemTime>,
+    },
+    Stdin(watch::Receiver<Option<RgbImage>>),
+}
+
+impl Frames {
+    fn open(source: Source) -> Frames {
+        match source {
+            Source::Image(path) => Frames::File {
+                path,
+                modified: None,
+            },
+            Source::Stdin { width, height } => {
+                let (tx, rx) = watch::channel(None);
+                thread::spawn(move || {
+                    let mut stdin = io::stdin().lock();
+                    let mut buf = vec![0u8; (width * height * 3) as usize];
+                    while stdin.read_exact(&mut buf).is_ok() {
+                        let frame = RgbImage::from_raw(width, height, buf.clone());
+                        if tx.send(frame).is_err() {
+                            return;
+                        }
+                    }
+                });
+                Frames::Stdin(rx)
+            }
+        }
+    }
+
+    fn poll(&mut self) -> Frame {
+        match self {
+            Frames::File { path, modified } => {
+                let current = fs::metadata(&*path).and_then(|m| m.modified()).ok();
+                if current.is_some() && current == *modified {
+                    return Frame::Unchanged;
+                }
+                match image::open(&*path) {
+                    Ok(image) => {
+                        *modified = current;
+                        Frame::New(image.into_rgb8())
+                    }
+                    Err(e) => {
+                        // Screenshot tools may still be writing the file
+                        eprintln!("Ambient Warning: {}: {e}", path.display());
+                        Frame::Unchanged
+                    }
+                }
+            }
+            Frames::Stdin(rx) => {
+                // The reader may have sent its last frame and hung up since
+                // the previous tick, so take that frame before ending
+                let closed = rx.has_changed().is_err();
+                let frame = rx.borrow_and_update();
+                match &*frame {
+                    Some(image) if frame.has_changed() => Frame::New(image.clone()),
+                    _ if closed => Frame::Ended,
+                    _ => Frame::Unchanged,
+                }
+            }
+        }
+    }
+}
+
+/// One smoothing step from the color on the strip towards `target`.
+fn ease(current: Option<Color>, target: Color, smoothing: f64) -> Color {
+    match current {
+        Some(c) => c.lerp(target, 1.0 - smoothing.clamp(0.0, 0.99)),
+        None => target,
+    }
+}
+
+/// Pushes the source's color to the strip every `options.interval` until the
+/// source ends or Ctrl-C, easing towards it according to `options.smoothing`.
+pub async fn run(
+    source: Source,
+    options: Options,
+    bluetooth: &BluetoothConnection,
+) -> Result<(), String> {
+    let mut frames = Frames::open(source);
+    let mut ticker = time::interval(options.interval.max(Duration::from_millis(10)));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    let mut target = None;
+    let mut current: Option<Color> = None;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut ctrl_c => break,
+        }
+        match frames.poll() {
+            Frame::New(image) => target = Some(sample(&image, &options)),
+            Frame::Unchanged => {}
+            Frame::Ended => break,
+        }
+        let Some(target) = target else {
+            continue;
+        };
+        let next = ease(current, target, options.smoothing);
+        if current != Some(next) {
+            bluetooth.write(Controller::color(next)).await?;
+            current = Some(next);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    fn options(method: Method) -> Options {
+        Options {
+            method,
+            region: Region::default(),
+            edge: 0.1,
+            saturation: 1.0,
+            smoothing: 0.0,
+            interval: Duration::from_millis(100),
+        }
+    }
+
+    /// A 10x10 image that is red on the left half and blue on the right.
+    fn halves() -> RgbImage {
+        RgbImage::from_fn(10, 10, |x, _| {
+            if x < 5 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn average_mixes_the_whole_region() {
+        let color = sample(&halves(), &options(Method::Average));
+        assert!(color.r.abs_diff(127) <= 2 && color.g <= 2 && color.b.abs_diff(127) <= 2);
+    }
+
+    #[test]
+    fn region_selects_part_of_the_frame() {
+        let mut options = options(Method::Average);
+        options.region = parse_region("0.5,0,0.5,1").unwrap();
+        let color = sample(&halves(), &options);
+        assert!(color.r <= 2 && color.g <= 2 && color.b >= 253);
+    }
+
+    #[test]
+    fn edge_ignores_the_center() {
+        // Green frame around a white center
+        let image = RgbImage::from_fn(20, 20, |x, y| {
+            if (2..18).contains(&x) && (2..18).contains(&y) {
+                Rgb([255, 255, 255])
+            } else {
+                Rgb([0, 255, 0])
+            }
+        });
+        let color = sample(&image, &options(Method::Edge));
+        assert!(color.r <= 2 && color.g >= 253 && color.b <= 2);
+        let color = sample(&image, &options(Method::Average));
+        assert!(color.r > 100);
+    }
+
+    #[test]
+    fn dominant_picks_the_most_common_color() {
+        let image = RgbImage::from_fn(10, 10, |x, _| {
+            if x < 3 {
+                Rgb([255, 0, 0])
+            } else {
+                Rgb([0, 0, 255])
+            }
+        });
+        let color = sample(&image, &options(Method::Dominant));
+        assert!(color.r <= 2 && color.b >= 253);
+    }
+
+    #[test]
+    fn regions_at_the_far_edge_sample_one_pixel() {
+        let region = Region {
+            x: 1.0,
+            y: 1.0,
+            width: 0.0,
+            height: 0.0,
+        };
+        let options = Options {
+            region,
+            ..options(Method::Average)
+        };
+        let color = sample(&halves(), &options);
+        assert!(color.b >= 253);
+        assert_eq!(sample(&RgbImage::new(0, 0), &options), Color::default());
+    }
+
+    #[test]
+    fn parse_region_rejects_regions_outside_the_frame() {
+        assert!(parse_region("0.7,0,0.3,1").is_ok());
+        assert!(parse_region("1,0,0.5,1").is_err());
+        assert!(parse_region("0,0.6,1,0.5").is_err());
+        assert!(parse_region("0,0,0,1").is_err());
+        assert!(parse_region("0,0,1").is_err());
+        assert!(parse_region("a,0,1,1").is_err());
+    }
+
+    #[test]
+    fn saturation_scales_chroma() {
+        let image = RgbImage::from_pixel(4, 4, Rgb([200, 100, 100]));
+        let mut options = options(Method::Average);
+        options.saturation = 0.0;
+        let gray = sample(&image, &options);
+        assert!(gray.r.abs_diff(gray.g) <= 2 && gray.g.abs_diff(gray.b) <= 2);
+        options.saturation = 1.0;
+        assert_eq!(
+            sample(&image, &options),
+            Color {
+                r: 200,
+                g: 100,
+                b: 100
+            }
+        );
+    }
+
+    #[test]
+    fn smoothing_eases_towards_the_target() {
+        let black = Color::default();
+        let white = Color {
+            r: 255,
+            g: 255,
+            b: 255,
+        };
+        assert_eq!(ease(None, white, 0.5), white);
+        assert_eq!(ease(Some(black), white, 0.0), white);
+        let half = ease(Some(black), white, 0.5);
+        let slow = ease(Some(black), white, 0.9);
+        assert!(black < slow && slow < half && half < white);
+    }
+
+    #[test]
+    fn stdin_frames_are_sampled_after_the_stream_ends() {
+        let (tx, rx) = watch::channel(None);
+        let mut frames = Frames::Stdin(rx);
+        assert!(matches!(frames.poll(), Frame::Unchanged));
+        tx.send(Some(halves())).unwrap();
+        drop(tx);
+        assert!(matches!(frames.poll(), Frame::New(_)));
+        assert!(matches!(frames.poll(), Frame::Ended));
+    }
+}
@@ -0,0 +1,367 @@
+use std::{
+    f64::consts::PI,
+    time::{Duration, Instant},
+};
+
+use clap::ValueEnum;
+use tokio::time::{self, MissedTickBehavior};
+
+use crate::{
+    bluetooth::BluetoothConnection,
+    controller::{Color, Controller},
+};
+
+/// Upper bound on frames per second; the BLE link drops writes beyond this.
+pub const MAX_FPS: u32 = 30;
+pub const DEFAULT_FPS: u32 = 20;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Effect {
+    Fade,
+    Breathe,
+    Candle,
+    Rainbow,
+    Strobe,
+}
+
+impl Effect {
+    pub fn name(self) -> &'static str {
+        match self {
+            Effect::Fade => "Fade",
+            Effect::Breathe => "Breathe",
+            Effect::Candle => "Candle",
+            Effect::Rainbow => "Rainbow",
+            Effect::Strobe => "Strobe",
+        }
+    }
+
+    /// Color used when the effect is started without an explicit target.
+    pub fn default_color(self) -> Color {
+        match self {
+            Effect::Candle => Color {
+                r: 255,
+                g: 147,
+                b: 41,
+            },
+            _ => Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Easing {
+    Linear,
+    EaseIn,
+    EaseOut,
+    EaseInOut,
+    Sine,
+}
+
+impl Easing {
+    pub fn apply(self, t: f64) -> f64 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Easing::EaseInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - (-2.0 * t + 2.0).powi(2) / 2.0
+                }
+            }
+            Easing::Sine => (1.0 - (PI * t).cos()) / 2.0,
+        }
+    }
+}
+
+/// A client-side effect rendered as a stream of static color frames.
+///
+/// `duration` is one cycle for fade, breathe and rainbow, and the total run
+/// time for candle and strobe. `period` is the flicker interval of candle and
+/// the flash length of strobe. Looping fades ping-pong between the colors.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub effect: Effect,
+    pub from: Color,
+    pub to: Color,
+    pub duration: Duration,
+    pub period: Duration,
+    pub easing: Easing,
+    pub looping: bool,
+}
+
+impl Animation {
+    pub fn finished(&self, elapsed: Duration) -> bool {
+        !self.looping && elapsed >= self.duration
+    }
+
+    pub fn frame(&self, elapsed: Duration) -> Color {
+        let elapsed = if self.looping {
+            elapsed
+        } else {
+            elapsed.min(self.duration)
+        };
+        let total = self.duration.as_secs_f64().max(0.001);
+        let t = elapsed.as_secs_f64() / total;
+        let (mut cycle, mut p) = (t.floor(), t.fract());
+        if !self.looping && t >= 1.0 {
+            // Hold the end of the only cycle rather than starting the next
+            (cycle, p) = (0.0, 1.0);
+        }
+
+        match self.effect {
+            Effect::Fade => {
+                if cycle as u64 % 2 == 1 {
+                    p = 1.0 - p;
+                }
+                self.from.lerp(self.to, self.easing.apply(p))
+            }
+            Effect::Breathe => {
+                let level = self.easing.apply(1.0 - (2.0 * p - 1.0).abs());
+                self.to.scaled((level * 255.0).round() as u8)
+            }
+            Effect::Candle => {
+                let step = elapsed.as_secs_f64() / self.period.as_secs_f64().max(0.001);
+                let k = step.floor() as u64;
+                let level = flicker(k) + (flicker(k + 1) - flicker(k)) * step.fract();
+                self.to.scaled((level * 255.0).round() as u8)
+            }
+            Effect::Rainbow => Color::from_hsv(360.0 * p, 1.0, 1.0),
+            Effect::Strobe => {
+                let flashes = elapsed.as_millis() / self.period.as_millis().max(1);
+                if flashes.is_multiple_of(2) {
+                    self.to
+                } else {
+                    Color::default()
+                }
+            }
+        }
+    }
+}
+
+/// Deterministic pseudo-random brightness in 0.55-1.0 for flicker step `k`.
+fn flicker(k: u64) -> f64 {
+    let mut z = k.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^= z >> 31;
+    0.55 + 0.45 * (z >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Parses durations such as `500ms`, `2s`, `30m` or `1h`; bare numbers are milliseconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let split = s
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(s.len());
+    let (value, unit) = s.split_at(split);
+    let value: f64 = value
+        .parse()
+        .map_err(|_| format!("invalid duration '{s}'"))?;
+    let seconds = match unit.trim() {
+        "" | "ms" => value / 1000.0,
+        "s" => value,
+        "m" | "min" => value * 60.0,
+        "h" => value * 3600.0,
+        other => return Err(format!("unknown duration unit '{other}' in '{s}'")),
+    };
+    Duration::try_from_secs_f64(seconds).map_err(|_| format!("invalid duration '{s}'"))
+}
+
+/// Streams the animation to the strip at up to `fps` frames per second until
+/// it finishes or Ctrl-C is pressed. Unchanged frames are not re-sent.
+/// Returns the last color written, if any.
+pub async fn run(
+    animation: &Animation,
+    fps: u32,
+    bluetooth: &BluetoothConnection,
+) -> Result<Option<Color>, String> {
+    let fps = fps.clamp(1, MAX_FPS);
+    let mut ticker = time::interval(Duration::from_secs_f64(1.0 / fps as f64));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    let start = Instant::now();
+    let mut last = None;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut ctrl_c => break,
+        }
+        let elapsed = start.elapsed();
+        let color = animation.frame(elapsed);
+        if last != Some(color) {
+            bluetooth.write(Controller::color(color)).await?;
+            last = Some(color);
+        }
+        if animation.finished(elapsed) {
+            break;
+        }
+    }
+    Ok(last)
+}
+
+/// Fades the strip from `from` to `to` over `duration`, or writes `to`
+/// directly when there is nothing to interpolate. Returns the color left on
+/// the strip, which falls short of `to` if Ctrl-C cut the fade short.
+pub async fn transition(
+    bluetooth: &BluetoothConnection,
+    from: Color,
+    to: Color,
+    duration: Duration,
+) -> Result<Color, String> {
+    if duration.is_zero() || from == to {
+        bluetooth.write(Controller::color(to)).await?;
+        return Ok(to);
+    }
+    let animation = Animation {
+        effect: Effect::Fade,
+        from,
+        to,
+        duration,
+        period: duration,
+        easing: Easing::Linear,
+        looping: false,
+    };
+    Ok(run(&animation, DEFAULT_FPS, bluetooth)
+        .await?
+        .unwrap_or(from))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_duration_units() {
+        assert_eq!(parse_duration("500ms"), Ok(Duration::from_millis(500)));
+        assert_eq!(parse_duration("2s"), Ok(Duration::from_secs(2)));
+        assert_eq!(parse_duration("1.5s"), Ok(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("5min"), Ok(Duration::from_secs(300)));
+        assert_eq!(parse_duration(" 1h "), Ok(Duration::from_secs(3600)));
+    }
+
+    #[test]
+    fn bare_durations_are_milliseconds() {
+        assert_eq!(parse_duration("250"), Ok(Duration::from_millis(250)));
+        assert_eq!(parse_duration("0"), Ok(Duration::ZERO));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert_eq!(
+            parse_duration("2d"),
+            Err("unknown duration unit 'd' in '2d'".to_string())
+        );
+        assert_eq!(parse_duration("s"), Err("invalid duration 's'".to_string()));
+        assert_eq!(
+            parse_duration("99999999999999999999h"),
+            Err("invalid duration '99999999999999999999h'".to_string())
+        );
+    }
+
+    const RED: Color = Color { r: 255, g: 0, b: 0 };
+    const BLUE: Color = Color { r: 0, g: 0, b: 255 };
+
+    fn animation(effect: Effect, looping: bool) -> Animation {
+        Animation {
+            effect,
+            from: RED,
+            to: BLUE,
+            duration: Duration::from_secs(1),
+            period: Duration::from_millis(100),
+            easing: Easing::Linear,
+            looping,
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn easings_keep_their_endpoints() {
+        let cases = [
+            (Easing::Linear, 0.5),
+            (Easing::EaseIn, 0.25),
+            (Easing::EaseOut, 0.75),
+            (Easing::EaseInOut, 0.5),
+            (Easing::Sine, 0.5),
+        ];
+        for (easing, half) in cases {
+            assert_eq!(easing.apply(0.0), 0.0, "{easing:?}");
+            assert!((easing.apply(0.5) - half).abs() < 1e-12, "{easing:?}");
+            assert!((easing.apply(1.0) - 1.0).abs() < 1e-12, "{easing:?}");
+            // Progress outside 0-1 is clamped
+            assert_eq!(easing.apply(-1.0), easing.apply(0.0), "{easing:?}");
+            assert_eq!(easing.apply(2.0), easing.apply(1.0), "{easing:?}");
+        }
+    }
+
+    #[test]
+    fn fade_runs_from_one_color_to_the_other() {
+        let fade = animation(Effect::Fade, false);
+        assert_eq!(fade.frame(ms(0)), RED);
+        assert_eq!(fade.frame(ms(1000)), BLUE);
+        assert_eq!(fade.frame(ms(500)), RED.lerp(BLUE, 0.5));
+    }
+
+    #[test]
+    fn looping_fade_runs_back_on_odd_cycles() {
+        let fade = animation(Effect::Fade, true);
+        assert_eq!(fade.frame(ms(1000)), BLUE);
+        assert_eq!(fade.frame(ms(1250)), fade.frame(ms(750)));
+        assert_eq!(fade.frame(ms(1250)), RED.lerp(BLUE, 0.75));
+        assert_eq!(fade.frame(ms(2000)), RED);
+        assert_eq!(fade.frame(ms(2250)), fade.frame(ms(250)));
+        assert!(!fade.finished(ms(5000)));
+    }
+
+    #[test]
+    fn breathe_peaks_at_the_half_cycle() {
+        let breathe = animation(Effect::Breathe, true);
+        assert_eq!(breathe.frame(ms(0)), Color::default());
+        assert_eq!(breathe.frame(ms(250)), BLUE.scaled(128));
+        assert_eq!(breathe.frame(ms(500)), BLUE);
+        assert_eq!(breathe.frame(ms(750)), BLUE.scaled(128));
+        assert_eq!(breathe.frame(ms(1500)), BLUE);
+    }
+
+    #[test]
+    fn strobe_flashes_every_period() {
+        let strobe = animation(Effect::Strobe, false);
+        assert_eq!(strobe.frame(ms(0)), BLUE);
+        assert_eq!(strobe.frame(ms(99)), BLUE);
+        assert_eq!(strobe.frame(ms(100)), Color::default());
+        assert_eq!(strobe.frame(ms(199)), Color::default());
+        assert_eq!(strobe.frame(ms(200)), BLUE);
+    }
+
+    #[test]
+    fn finished_animations_hold_their_last_frame() {
+        for effect in [
+            Effect::Fade,
+            Effect::Breathe,
+            Effect::Candle,
+            Effect::Rainbow,
+            Effect::Strobe,
+        ] {
+            let animation = animation(effect, false);
+            assert!(!animation.finished(ms(999)), "{effect:?}");
+            assert!(animation.finished(ms(1000)), "{effect:?}");
+            let last = animation.frame(ms(1000));
+            assert_eq!(animation.frame(ms(1500)), last, "{effect:?}");
+            assert_eq!(animation.frame(ms(60_000)), last, "{effect:?}");
+        }
+        assert_eq!(animation(Effect::Fade, false).frame(ms(3000)), BLUE);
+    }
+}
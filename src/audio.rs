@@ -0,0 +1,445 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read},
+    path::PathBuf,
+    process::{Command, Stdio},
+    sync::Arc,
+    thread,
+    time::{Duration, Instant},
+};
+
+use realfft::{RealFftPlanner, RealToComplex};
+use tokio::{
+    sync::mpsc,
+    time::{self, MissedTickBehavior},
+};
+
+use crate::{
+    bluetooth::BluetoothConnection,
+    controller::{Color, Controller},
+};
+
+/// Samples per analysis window.
+const FFT_SIZE: usize = 1024;
+/// Analysis windows of bass history used as the beat detection baseline
+/// (~1s at 44.1kHz, with windows overlapping by half).
+const BEAT_HISTORY: usize = 86;
+/// Bass energy relative to the recent average that counts as a beat.
+const BEAT_THRESHOLD: f32 = 1.5;
+
+pub const PULSE_MONITOR: &str = "@DEFAULT_MONITOR@";
+
+pub enum Source {
+    /// A WAV file, played back in real time.
+    Wav(PathBuf),
+    /// Raw signed 16-bit little-endian PCM on stdin.
+    Stdin { rate: u32, channels: u16 },
+    /// A PulseAudio / PipeWire source recorded through `parec`.
+    Pulse { device: String, rate: u32 },
+}
+
+/// Energy per band, normalized against recent peaks, plus beat detection.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Levels {
+    pub bass: f32,
+    pub mid: f32,
+    pub treble: f32,
+    pub beat: bool,
+}
+
+pub struct Analyzer {
+    fft: Arc<dyn RealToComplex<f32>>,
+    window: Vec<f32>,
+    sample_rate: u32,
+    peaks: [f32; 3],
+    bass_history: VecDeque<f32>,
+    pulse: f32,
+}
+
+impl Analyzer {
+    pub fn new(sample_rate: u32) -> Analyzer {
+        let window = (0..FFT_SIZE)
+            .map(|i| {
+                let phase = 2.0 * std::f32::consts::PI * i as f32 / (FFT_SIZE - 1) as f32;
+                0.5 - 0.5 * phase.cos()
+            })
+            .collect();
+        Analyzer {
+            fft: RealFftPlanner::new().plan_fft_forward(FFT_SIZE),
+            window,
+            sample_rate,
+            peaks: [1e-3; 3],
+            bass_history: VecDeque::with_capacity(BEAT_HISTORY),
+            pulse: 0.0,
+        }
+    }
+
+    /// Analyzes one window of `FFT_SIZE` mono samples in -1.0..1.0.
+    pub fn process(&mut self, samples: &[f32]) -> Levels {
+        let mut input: Vec<f32> = samples
+            .iter()
+            .zip(&self.window)
+            .map(|(s, w)| s * w)
+            .collect();
+        let mut spectrum = self.fft.make_output_vec();
+        if self.fft.process(&mut input, &mut spectrum).is_err() {
+            return Levels::default();
+        }
+
+        let bin_hz = self.sample_rate as f32 / FFT_SIZE as f32;
+        let band = |low: f32, high: f32| {
+            let from = ((low / bin_hz) as usize).max(1);
+            let to = ((high / bin_hz) as usize).min(spectrum.len());
+            // Bands above the Nyquist frequency have no bins
+            if from >= to {
+                return 0.0;
+            }
+            spectrum[from..to]
+                .iter()
+                .map(|c| c.norm_sqr())
+                .sum::<f32>()
+                .sqrt()
+        };
+        let energy = [band(20.0, 250.0), band(250.0, 2000.0), band(2000.0, 8000.0)];
+
+        let mut levels = [0.0; 3];
+        for (i, e) in energy.iter().enumerate() {
+            // Peaks decay slowly so quiet passages still use the full range
+            self.peaks[i] = (self.peaks[i] * 0.995).max(*e).max(1e-3);
+            levels[i] = (e / self.peaks[i]).clamp(0.0, 1.0);
+        }
+
+        let average = self.bass_history.iter().sum::<f32>() / self.bass_history.len().max(1) as f32;
+        let beat = self.bass_history.len() == BEAT_HISTORY
+            && energy[0] > average * BEAT_THRESHOLD
+            && levels[0] > 0.3;
+        if self.bass_history.len() == BEAT_HISTORY {
+            self.bass_history.pop_front();
+        }
+        self.bass_history.push_back(energy[0]);
+
+        Levels {
+            bass: levels[0],
+            mid: levels[1],
+            treble: levels[2],
+            beat,
+        }
+    }
+
+    /// Maps the mix of bass/mid/treble to red/green/blue at full strength,
+    /// and the loudest band to brightness, with beats flashing to full
+    /// brightness and decaying back. The strip has no brightness command, so
+    /// brightness scales the color as it does for the other commands.
+    pub fn color(&mut self, levels: Levels) -> Color {
+        let level = levels.bass.max(levels.mid).max(levels.treble);
+        self.pulse = if levels.beat { 1.0 } else { self.pulse * 0.85 };
+        let brightness = (level.max(self.pulse) * 255.0).round() as u8;
+        let channel = |v: f32| (v / level.max(1e-3) * 255.0).round() as u8;
+        Color {
+            r: channel(levels.bass),
+            g: channel(levels.mid),
+            b: channel(levels.treble),
+        }
+        .scaled(brightness)
+    }
+}
+
+/// Sends mono f32 chunks until the source ends or the receiver is dropped.
+fn pump(mut reader: impl Read, channels: u16, tx: mpsc::Sender<Vec<f32>>) -> io::Result<()> {
+    let frame_bytes = 2 * channels.max(1) as usize;
+    let mut buf = vec![0u8; frame_bytes * FFT_SIZE / 2];
+    loop {
+        let n = read_full(&mut reader, &mut buf)?;
+        if n < frame_bytes {
+            return Ok(());
+        }
+        let chunk = buf[..n - n % frame_bytes]
+            .chunks_exact(frame_bytes)
+            .map(|frame| {
+                let sum: f32 = frame
+                    .chunks_exact(2)
+                    .map(|s| i16::from_le_bytes([s[0], s[1]]) as f32 / 32768.0)
+                    .sum();
+                sum / channels.max(1) as f32
+            })
+            .collect();
+        if tx.blocking_send(chunk).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..])? {
+            0 => break,
+            n => filled += n,
+        }
+    }
+    Ok(filled)
+}
+
+fn pump_wav(path: PathBuf, tx: mpsc::Sender<Vec<f32>>) -> Result<u32, String> {
+    let reader = hound::WavReader::open(&path)
+        .map_err(|e| format!("Audio Error: {}: {e}", path.display()))?;
+    let spec = reader.spec();
+    let channels = spec.channels.max(1) as usize;
+    let scale = match spec.sample_format {
+        hound::SampleFormat::Float => 1.0,
+        hound::SampleFormat::Int => (1u64 << (spec.bits_per_sample - 1)) as f32,
+    };
+
+    thread::spawn(move || {
+        let mut reader = reader;
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().filter_map(Result::ok).collect(),
+            hound::SampleFormat::Int => reader
+                .samples::<i32>()
+                .filter_map(Result::ok)
+                .map(|s| s as f32 / scale)
+                .collect(),
+        };
+        // Pace playback so the lights follow the file as if it were playing
+        let start = Instant::now();
+        let chunk_frames = FFT_SIZE / 2;
+        for (i, chunk) in samples.chunks(chunk_frames * channels).enumerate() {
+            let due = Duration::from_secs_f64((i * chunk_frames) as f64 / spec.sample_rate as f64);
+            if let Some(wait) = due.checked_sub(start.elapsed()) {
+                thread::sleep(wait);
+            }
+            let mono = chunk
+                .chunks(channels)
+                .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                .collect();
+            if tx.blocking_send(mono).is_err() {
+                return;
+            }
+        }
+    });
+    Ok(spec.sample_rate)
+}
+
+/// Starts reading `source` on a background thread and returns its sample rate.
+fn spawn_source(source: Source, tx: mpsc::Sender<Vec<f32>>) -> Result<u32, String> {
+    match source {
+        Source::Wav(path) => pump_wav(path, tx),
+        Source::Stdin { rate, channels } => {
+            thread::spawn(move || {
+                if let Err(e) = pump(io::stdin().lock(), channels, tx) {
+                    eprintln!("Audio Error: stdin: {e}");
+                }
+            });
+            Ok(rate)
+        }
+        Source::Pulse { device, rate } => {
+            let mut child = Command::new("parec")
+                .args([
+                    "--raw",
+                    "--format=s16le",
+                    "--channels=1",
+                    &format!("--rate={rate}"),
+                    &format!("--device={device}"),
+                ])
+                .stdout(Stdio::piped())
+                .spawn()
+                .map_err(|e| format!("Audio Error: could not start parec: {e}"))?;
+            let stdout = child
+                .stdout
+                .take()
+                .ok_or("Audio Error: parec has no stdout")?;
+            thread::spawn(move || {
+                if let Err(e) = pump(stdout, 1, tx) {
+                    eprintln!("Audio Error: parec: {e}");
+                }
+                let _ = child.kill();
+                let _ = child.wait();
+            });
+            Ok(rate)
+        }
+    }
+}
+
+/// Drives the strip from host audio until the source ends or Ctrl-C,
+/// writing at most `fps` color frames per second.
+pub async fn run(source: Source, fps: u32, bluetooth: &BluetoothConnection) -> Result<(), String> {
+    let (tx, mut rx) = mpsc::channel(16);
+    let sample_rate = spawn_source(source, tx)?;
+    let mut analyzer = Analyzer::new(sample_rate);
+
+    let mut ticker = time::interval(Duration::from_secs_f64(1.0 / fps.max(1) as f64));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    let mut buffer: Vec<f32> = Vec::with_capacity(FFT_SIZE * 2);
+    let mut latest = None;
+    let mut last = None;
+    loop {
+        tokio::select! {
+            chunk = rx.recv() => {
+                let Some(chunk) = chunk else {
+                    break;
+                };
+                buffer.extend(chunk);
+                while buffer.len() >= FFT_SIZE {
+                    let levels = analyzer.process(&buffer[..FFT_SIZE]);
+                    latest = Some(analyzer.color(levels));
+                    buffer.drain(..FFT_SIZE / 2);
+                }
+            }
+            _ = ticker.tick() => {
+                if let Some(color) = latest.take()
+                    && last != Some(color)
+                {
+                    bluetooth.write(Controller::color(color)).await?;
+                    last = Some(color);
+                }
+            }
+            _ = &mut ctrl_c => break,
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, f32::consts::PI};
+
+    use super::*;
+
+    const RATE: u32 = 44100;
+
+    /// Writes `seconds` of 16-bit mono audio given by `signal(t)` to a WAV
+    /// fixture in the temp directory.
+    fn wav(name: &str, seconds: f32, signal: impl Fn(f32) -> f32) -> PathBuf {
+        let path = env::temp_dir().join(format!("batlights-{}-{name}.wav", std::process::id()));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: RATE,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..(seconds * RATE as f32) as usize {
+            let sample = signal(i as f32 / RATE as f32).clamp(-1.0, 1.0);
+            writer
+                .write_sample((sample * i16::MAX as f32) as i16)
+                .unwrap();
+        }
+        writer.finalize().unwrap();
+        path
+    }
+
+    /// Runs the analyzer over a WAV fixture with the same overlap as `run`.
+    fn analyze(path: &PathBuf) -> (Analyzer, Vec<Levels>) {
+        let mut reader = hound::WavReader::open(path).unwrap();
+        let samples: Vec<f32> = reader
+            .samples::<i16>()
+            .map(|s| s.unwrap() as f32 / 32768.0)
+            .collect();
+        std::fs::remove_file(path).unwrap();
+        let mut analyzer = Analyzer::new(reader.spec().sample_rate);
+        let levels = samples
+            .windows(FFT_SIZE)
+            .step_by(FFT_SIZE / 2)
+            .map(|window| analyzer.process(window))
+            .collect();
+        (analyzer, levels)
+    }
+
+    fn sine(hz: f32) -> impl Fn(f32) -> f32 {
+        move |t| 0.5 * (2.0 * PI * hz * t).sin()
+    }
+
+    /// Index of the band with the most energy seen.
+    fn loudest_band(hz: f32) -> usize {
+        let (analyzer, _) = analyze(&wav(&format!("sine{hz}"), 1.0, sine(hz)));
+        let peaks = analyzer.peaks;
+        (0..3)
+            .max_by(|a, b| peaks[*a].total_cmp(&peaks[*b]))
+            .unwrap()
+    }
+
+    #[test]
+    fn sines_land_in_their_band() {
+        assert_eq!(loudest_band(100.0), 0);
+        assert_eq!(loudest_band(1000.0), 1);
+        assert_eq!(loudest_band(5000.0), 2);
+    }
+
+    #[test]
+    fn silence_is_dark() {
+        let (mut analyzer, levels) = analyze(&wav("silence", 2.0, |_| 0.0));
+        for level in levels {
+            assert_eq!((level.bass, level.mid, level.treble), (0.0, 0.0, 0.0));
+            assert!(!level.beat);
+            assert_eq!(analyzer.color(level), Color::default());
+        }
+    }
+
+    #[test]
+    fn bands_set_the_hue_and_beats_the_brightness() {
+        let mut analyzer = Analyzer::new(RATE);
+        let orange = Color {
+            r: 255,
+            g: 128,
+            b: 0,
+        };
+        let mut levels = Levels {
+            bass: 0.5,
+            mid: 0.25,
+            treble: 0.0,
+            beat: false,
+        };
+        assert_eq!(analyzer.color(levels), orange.scaled(128));
+        levels.beat = true;
+        assert_eq!(analyzer.color(levels), orange);
+        // The flash decays back to the loudest band
+        levels.beat = false;
+        assert_eq!(analyzer.color(levels), orange.scaled(217));
+        for _ in 0..20 {
+            analyzer.color(levels);
+        }
+        assert_eq!(analyzer.color(levels), orange.scaled(128));
+    }
+
+    #[test]
+    fn steady_tones_have_no_beats() {
+        let (_, levels) = analyze(&wav("steady", 3.0, sine(80.0)));
+        assert!(levels.iter().all(|l| !l.beat));
+    }
+
+    #[test]
+    fn kicks_are_detected_as_beats() {
+        // 60Hz kicks lasting 50ms, twice a second
+        let kicks = |t: f32| {
+            if t % 0.5 < 0.05 {
+                sine(60.0)(t) * 1.8
+            } else {
+                0.0
+            }
+        };
+        let (_, levels) = analyze(&wav("kicks", 4.0, kicks));
+        let onsets = levels
+            .windows(2)
+            .filter(|pair| pair[1].beat && !pair[0].beat)
+            .count();
+        // The first second fills the history before beats can be detected
+        assert!((5..=6).contains(&onsets), "{onsets} beats");
+    }
+
+    #[test]
+    fn low_sample_rates_do_not_panic() {
+        let window: Vec<f32> = (0..FFT_SIZE)
+            .map(|i| sine(440.0)(i as f32 / 3000.0))
+            .collect();
+        // Treble starts above the Nyquist frequency of these rates
+        for rate in [0, 3000] {
+            let levels = Analyzer::new(rate).process(&window);
+            assert_eq!(levels.treble, 0.0);
+        }
+        Analyzer::new(8000).process(&window);
+    }
+}
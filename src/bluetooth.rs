@@ -1,33 +1,76 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use btleplug::api::{Characteristic, WriteType};
 use btleplug::{
     api::{Central, Manager as _, Peripheral as _, ScanFilter},
-    platform::{Manager, Peripheral},
+    platform::{Adapter, Manager, Peripheral},
 };
 use tokio::time;
 use uuid::Uuid;
 
+use crate::calibration::Calibration;
+
 pub struct BluetoothConnection {
     pub peripheral: Peripheral,
     pub characteristic: Characteristic,
+    /// Correction applied to every color frame written.
+    pub calibration: Calibration,
+}
+
+/// A peripheral seen while scanning.
+#[derive(Debug, Clone)]
+pub struct Nearby {
+    pub mac: String,
+    pub name: Option<String>,
+    pub rssi: Option<i16>,
+}
+
+/// The first bluetooth adapter of the system.
+pub async fn adapter() -> Result<Adapter, String> {
+    let manager = Manager::new().await.map_err(|e| format!("BT Error: {e}"))?;
+    let adapters = manager
+        .adapters()
+        .await
+        .map_err(|e| format!("BT Error: {e}"))?;
+    adapters
+        .into_iter()
+        .next()
+        .ok_or("BT Error: No bluetooth adapter found".to_string())
+}
+
+/// Peripherals the adapter currently knows about, strongest signal first.
+pub async fn nearby(adapter: &Adapter) -> Result<Vec<Nearby>, String> {
+    let peripherals = adapter
+        .peripherals()
+        .await
+        .map_err(|e| format!("BT Error: {e}"))?;
+    let mut nearby = Vec::with_capacity(peripherals.len());
+    for peripheral in peripherals {
+        let properties = peripheral.properties().await.ok().flatten();
+        nearby.push(Nearby {
+            mac: peripheral.address().to_string(),
+            name: properties.as_ref().and_then(|p| p.local_name.clone()),
+            rssi: properties.and_then(|p| p.rssi),
+        });
+    }
+    nearby.sort_by_key(|n| std::cmp::Reverse(n.rssi.unwrap_or(i16::MIN)));
+    Ok(nearby)
 }
 
 impl BluetoothConnection {
     pub async fn new(mac: String, data_uuid: String) -> Result<BluetoothConnection, String> {
-        let manager = Manager::new().await.map_err(|e| format!("BT Error: {e}"))?;
-        let adapters = manager
-            .adapters()
-            .await
-            .map_err(|e| format!("BT Error: {e}"))?;
-        let adapter = adapters
-            .into_iter()
-            .next()
-            .ok_or("BT Error: No bluetooth adapter found")?;
-
+        let adapter = adapter().await?;
         let _ = adapter.start_scan(ScanFilter::default()).await;
         time::sleep(Duration::from_millis(200)).await;
+        Self::connect(&adapter, mac, data_uuid).await
+    }
 
+    /// Connects to a peripheral `adapter` has already seen while scanning.
+    pub async fn connect(
+        adapter: &Adapter,
+        mac: String,
+        data_uuid: String,
+    ) -> Result<BluetoothConnection, String> {
         let peripheral = adapter
             .peripherals()
             .await
@@ -59,17 +102,48 @@ impl BluetoothConnection {
         Ok(BluetoothConnection {
             peripheral,
             characteristic: cmd_char,
+            calibration: Calibration::default(),
         })
     }
 
+    pub fn calibrated(mut self, calibration: Calibration) -> BluetoothConnection {
+        self.calibration = calibration;
+        self
+    }
+
     pub async fn write(&self, payload: [u8; 9]) -> Result<(), String> {
-        let _ = self
-            .peripheral
-            .write(&self.characteristic, &payload, WriteType::WithoutResponse)
-            .await;
+        let _ = self.try_write(payload).await;
         Ok(())
     }
 
+    /// Writes like `write` but reports failures, returning how long the
+    /// write took.
+    pub async fn try_write(&self, payload: [u8; 9]) -> Result<Duration, String> {
+        let payload = self.calibration.apply_frame(payload);
+        let started = Instant::now();
+        self.peripheral
+            .write(&self.characteristic, &payload, WriteType::WithoutResponse)
+            .await
+            .map_err(|e| format!("BT Write Error: {e}"))?;
+        Ok(started.elapsed())
+    }
+
+    pub async fn is_connected(&self) -> bool {
+        self.peripheral.is_connected().await.unwrap_or(false)
+    }
+
+    /// Connects again after the link dropped.
+    pub async fn reconnect(&self) -> Result<(), String> {
+        self.peripheral
+            .connect()
+            .await
+            .map_err(|e| format!("BT Error: {e}"))?;
+        self.peripheral
+            .discover_services()
+            .await
+            .map_err(|e| format!("BT Error: {e}"))
+    }
+
     pub async fn bye(&self) -> Result<(), String> {
         self.peripheral
             .disconnect()
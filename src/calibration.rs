@@ -0,0 +1,320 @@
+use std::io::{self, BufRead, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bluetooth::BluetoothConnection,
+    colors::kelvin_to_color,
+    controller::{Color, Controller},
+};
+
+const GAIN_STEP: f64 = 0.05;
+const GAMMA_STEP: f64 = 0.1;
+/// Gamma values the wizard allows and the config may set.
+pub const GAMMA_RANGE: (f64, f64) = (0.5, 4.0);
+
+/// Output correction for one strip: a gamma curve to make brightness steps
+/// look even, then per-channel gain to neutralize the LEDs' tint.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Calibration {
+    pub gamma: f64,
+    /// Red, green and blue multipliers, 0.0-1.0.
+    pub gain: [f64; 3],
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            gamma: 1.0,
+            gain: [1.0; 3],
+        }
+    }
+}
+
+impl Calibration {
+    pub fn is_identity(&self) -> bool {
+        *self == Calibration::default()
+    }
+
+    /// Checks values read from the config file, which the wizard keeps in
+    /// range itself.
+    pub fn validate(&self) -> Result<(), String> {
+        let (low, high) = GAMMA_RANGE;
+        if !(low..=high).contains(&self.gamma) {
+            return Err(format!(
+                "calibration gamma {} is outside {low}-{high}",
+                self.gamma
+            ));
+        }
+        Ok(())
+    }
+
+    /// Maps an intended color to the values that make the strip show it.
+    pub fn apply(&self, color: Color) -> Color {
+        if self.is_identity() {
+            return color;
+        }
+        let channel = |v: u8, gain: f64| {
+            let linear = (v as f64 / 255.0).powf(self.gamma);
+            (linear * gain.clamp(0.0, 1.0) * 255.0).round() as u8
+        };
+        Color {
+            r: channel(color.r, self.gain[0]),
+            g: channel(color.g, self.gain[1]),
+            b: channel(color.b, self.gain[2]),
+        }
+    }
+
+    /// Calibrates the color of a color frame; other frames pass through.
+    pub fn apply_frame(&self, mut payload: [u8; 9]) -> [u8; 9] {
+        if payload[2] == 0x07 {
+            let color = self.apply(Color {
+                r: payload[3],
+                g: payload[4],
+                b: payload[5],
+            });
+            payload[3..6].copy_from_slice(&[color.r, color.g, color.b]);
+        }
+        payload
+    }
+
+    fn adjust(&mut self, command: &str) -> bool {
+        let (channel, step) = match command {
+            "+" => {
+                self.gamma = (self.gamma + GAMMA_STEP).min(GAMMA_RANGE.1);
+                return true;
+            }
+            "-" => {
+                self.gamma = (self.gamma - GAMMA_STEP).max(GAMMA_RANGE.0);
+                return true;
+            }
+            "r+" => (0, GAIN_STEP),
+            "r-" => (0, -GAIN_STEP),
+            "g+" => (1, GAIN_STEP),
+            "g-" => (1, -GAIN_STEP),
+            "b+" => (2, GAIN_STEP),
+            "b-" => (2, -GAIN_STEP),
+            _ => return false,
+        };
+        self.gain[channel] = (self.gain[channel] + step).clamp(0.0, 1.0);
+        true
+    }
+}
+
+struct Step {
+    title: &'static str,
+    hint: &'static str,
+    color: Color,
+}
+
+fn steps() -> [Step; 4] {
+    let white = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+    [
+        Step {
+            title: "Full white",
+            hint: "Lower the strongest channel until the strip looks neutral, like paper.",
+            color: white,
+        },
+        Step {
+            title: "Mid grey (50%)",
+            hint: "Adjust gamma until it looks half as bright as full white ('w' to compare).",
+            color: white.scaled(128),
+        },
+        Step {
+            title: "Warm white (2700K)",
+            hint: "Should look like an incandescent bulb; fine-tune gains if it does not.",
+            color: kelvin_to_color(2700),
+        },
+        Step {
+            title: "Daylight (6500K)",
+            hint: "Should look like overcast daylight; fine-tune gains if it does not.",
+            color: kelvin_to_color(6500),
+        },
+    ]
+}
+
+fn read_command() -> Result<Option<String>, String> {
+    print!("> ");
+    io::stdout()
+        .flush()
+        .map_err(|e| format!("Calibrate Error: {e}"))?;
+    let mut line = String::new();
+    let n = io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(|e| format!("Calibrate Error: {e}"))?;
+    Ok((n > 0).then(|| line.trim().to_lowercase()))
+}
+
+/// Steps through reference colors on the strip, letting the user tune
+/// `calibration` from the terminal. Returns `None` if aborted.
+pub async fn wizard(
+    bluetooth: &mut BluetoothConnection,
+    mut calibration: Calibration,
+) -> Result<Option<Calibration>, String> {
+    println!("Commands: r+ r- g+ g- b+ b- (gain), + - (gamma), w (toggle full white),");
+    println!("          Enter (next step), q (quit without saving)");
+    bluetooth.write(Controller::power(true)).await?;
+    let steps = steps();
+    for (i, step) in steps.iter().enumerate() {
+        println!();
+        println!("Step {}/{}: {}", i + 1, steps.len(), step.title);
+        println!("{}", step.hint);
+        bluetooth.calibration = calibration;
+        bluetooth.write(Controller::color(step.color)).await?;
+        let mut comparing = false;
+        loop {
+            let Some(command) = read_command()? else {
+                return Ok(None);
+            };
+            match command.as_str() {
+                "" => break,
+                "q" => return Ok(None),
+                "w" => {
+                    comparing = !comparing;
+                    let color = if comparing {
+                        steps[0].color
+                    } else {
+                        step.color
+                    };
+                    bluetooth.write(Controller::color(color)).await?;
+                    continue;
+                }
+                command if calibration.adjust(command) => {}
+                _ => {
+                    println!("Unknown command '{command}'");
+                    continue;
+                }
+            }
+            let [r, g, b] = calibration.gain;
+            println!(
+                "gamma {:.1}  gain r {r:.2} g {g:.2} b {b:.2}",
+                calibration.gamma
+            );
+            comparing = false;
+            bluetooth.calibration = calibration;
+            bluetooth.write(Controller::color(step.color)).await?;
+        }
+    }
+    Ok(Some(calibration))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: Color = Color {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    #[test]
+    fn identity_changes_nothing() {
+        let color = Color {
+            r: 1,
+            g: 128,
+            b: 254,
+        };
+        assert_eq!(Calibration::default().apply(color), color);
+    }
+
+    #[test]
+    fn applies_gamma_then_gain() {
+        let gamma = Calibration {
+            gamma: 2.0,
+            gain: [1.0; 3],
+        };
+        let grey = Color {
+            r: 128,
+            g: 128,
+            b: 128,
+        };
+        // (128 / 255)^2 * 255 = 64.25
+        assert_eq!(
+            gamma.apply(grey),
+            Color {
+                r: 64,
+                g: 64,
+                b: 64
+            }
+        );
+        assert_eq!(gamma.apply(WHITE), WHITE);
+        assert_eq!(gamma.apply(Color::default()), Color::default());
+
+        let gain = Calibration {
+            gamma: 1.0,
+            gain: [1.0, 0.5, 0.0],
+        };
+        assert_eq!(
+            gain.apply(WHITE),
+            Color {
+                r: 255,
+                g: 128,
+                b: 0
+            }
+        );
+
+        let both = Calibration {
+            gamma: 2.0,
+            gain: [0.5, 1.0, 2.0],
+        };
+        // Gains above 1.0 are clamped
+        assert_eq!(
+            both.apply(grey),
+            Color {
+                r: 32,
+                g: 64,
+                b: 64
+            }
+        );
+    }
+
+    #[test]
+    fn only_color_frames_are_calibrated() {
+        let calibration = Calibration {
+            gamma: 2.0,
+            gain: [1.0, 0.5, 0.25],
+        };
+        let grey = Color {
+            r: 128,
+            g: 128,
+            b: 128,
+        };
+        assert_eq!(
+            calibration.apply_frame(Controller::color(grey)),
+            Controller::color(calibration.apply(grey))
+        );
+        for frame in [
+            Controller::power(true),
+            Controller::pattern(128),
+            Controller::mic(128),
+        ] {
+            assert_eq!(calibration.apply_frame(frame), frame);
+        }
+    }
+
+    #[test]
+    fn validates_gamma() {
+        assert!(Calibration::default().validate().is_ok());
+        for gamma in [0.5, 2.2, 4.0] {
+            let calibration = Calibration {
+                gamma,
+                ..Calibration::default()
+            };
+            assert!(calibration.validate().is_ok(), "{gamma}");
+        }
+        for gamma in [0.0, 0.4, 4.1, -1.0, f64::NAN] {
+            let calibration = Calibration {
+                gamma,
+                ..Calibration::default()
+            };
+            assert!(calibration.validate().is_err(), "{gamma}");
+        }
+    }
+}
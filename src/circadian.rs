@@ -0,0 +1,481 @@
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime, UtcOffset};
+
+use crate::{
+    animation::parse_duration,
+    bluetooth::BluetoothConnection,
+    clock,
+    colors::{KELVIN_RANGE, kelvin_to_color},
+    config::DeviceConfig,
+    state::{DeviceState, Mode, State},
+};
+
+const MINUTES_PER_DAY: f64 = 1440.0;
+
+/// `[circadian]` section of the config file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub latitude: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub longitude: Option<f64>,
+    /// How long to leave the lights alone after a manual change.
+    pub resume_after_minutes: u64,
+    /// Curve points; a built-in day curve is used when empty.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub points: Vec<Point>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            latitude: None,
+            longitude: None,
+            resume_after_minutes: 120,
+            points: vec![],
+        }
+    }
+}
+
+/// One point of the curve. `at` is `sunrise`, `noon`, `sunset` or `HH:MM`,
+/// optionally shifted by a duration such as `sunset-1h` or `sunrise+30m`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Point {
+    pub at: String,
+    pub kelvin: u32,
+    pub brightness: u8,
+}
+
+fn default_points() -> Vec<Point> {
+    let point = |at: &str, kelvin, brightness| Point {
+        at: at.to_string(),
+        kelvin,
+        brightness,
+    };
+    vec![
+        point("sunrise-30m", 2000, 30),
+        point("sunrise+1h", 4000, 200),
+        point("noon", 5000, 255),
+        point("sunset-1h", 4000, 230),
+        point("sunset+30m", 2700, 160),
+        point("22:30", 2000, 60),
+    ]
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Anchor {
+    Sunrise,
+    Noon,
+    Sunset,
+    /// Minutes after local midnight.
+    Clock(f64),
+}
+
+/// Sun events for one day, in minutes after local midnight.
+#[derive(Clone, Copy, Debug)]
+pub struct SunTimes {
+    pub sunrise: f64,
+    pub noon: f64,
+    pub sunset: f64,
+}
+
+/// Computes sunrise, solar noon and sunset with the NOAA approximation.
+/// Polar day and night collapse to a 24h or zero-length day around noon.
+pub fn sun_times(date: Date, offset: UtcOffset, latitude: f64, longitude: f64) -> SunTimes {
+    let gamma = 2.0 * std::f64::consts::PI / 365.0 * (date.ordinal() as f64 - 1.0);
+    let eqtime = 229.18
+        * (0.000075 + 0.001868 * gamma.cos()
+            - 0.032077 * gamma.sin()
+            - 0.014615 * (2.0 * gamma).cos()
+            - 0.040849 * (2.0 * gamma).sin());
+    let decl = 0.006918 - 0.399912 * gamma.cos() + 0.070257 * gamma.sin()
+        - 0.006758 * (2.0 * gamma).cos()
+        + 0.000907 * (2.0 * gamma).sin()
+        - 0.002697 * (3.0 * gamma).cos()
+        + 0.00148 * (3.0 * gamma).sin();
+    let lat = latitude.to_radians();
+    let cos_ha = 90.833_f64.to_radians().cos() / (lat.cos() * decl.cos()) - lat.tan() * decl.tan();
+    let ha = cos_ha.clamp(-1.0, 1.0).acos().to_degrees();
+
+    let local = offset.whole_seconds() as f64 / 60.0;
+    let noon = 720.0 - 4.0 * longitude - eqtime + local;
+    SunTimes {
+        sunrise: noon - 4.0 * ha,
+        noon,
+        sunset: noon + 4.0 * ha,
+    }
+}
+
+struct CurvePoint {
+    anchor: Anchor,
+    shift: f64,
+    kelvin: u32,
+    brightness: u8,
+}
+
+/// Kelvin and brightness over the day, interpolated linearly between points.
+pub struct Curve {
+    points: Vec<CurvePoint>,
+}
+
+fn parse_at(at: &str) -> Result<(Anchor, f64), String> {
+    let invalid = || format!("Circadian Error: invalid time '{at}'");
+    let s = at.trim().to_lowercase();
+    let split = s.find(['+', '-']).unwrap_or(s.len());
+    let (anchor, shift) = s.split_at(split);
+    let anchor = match anchor.trim() {
+        "sunrise" => Anchor::Sunrise,
+        "noon" => Anchor::Noon,
+        "sunset" => Anchor::Sunset,
+        clock => {
+            let (h, m) = clock.split_once(':').ok_or_else(invalid)?;
+            let (h, m): (u32, u32) = (
+                h.parse().map_err(|_| invalid())?,
+                m.parse().map_err(|_| invalid())?,
+            );
+            if h > 23 || m > 59 {
+                return Err(invalid());
+            }
+            Anchor::Clock((h * 60 + m) as f64)
+        }
+    };
+    let shift = match shift.split_at_checked(1) {
+        Some(("+", d)) => parse_duration(d).map_err(|_| invalid())?.as_secs_f64() / 60.0,
+        Some(("-", d)) => -parse_duration(d).map_err(|_| invalid())?.as_secs_f64() / 60.0,
+        _ => 0.0,
+    };
+    Ok((anchor, shift))
+}
+
+impl Curve {
+    pub fn parse(points: &[Point]) -> Result<Curve, String> {
+        let defaults = default_points();
+        let points = if points.is_empty() { &defaults } else { points };
+        let points = points
+            .iter()
+            .map(|p| {
+                if !(KELVIN_RANGE.0..=KELVIN_RANGE.1).contains(&p.kelvin) {
+                    return Err(format!(
+                        "Circadian Error: {}K at '{}' is outside {}K-{}K",
+                        p.kelvin, p.at, KELVIN_RANGE.0, KELVIN_RANGE.1
+                    ));
+                }
+                let (anchor, shift) = parse_at(&p.at)?;
+                Ok(CurvePoint {
+                    anchor,
+                    shift,
+                    kelvin: p.kelvin,
+                    brightness: p.brightness,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Curve { points })
+    }
+
+    /// Points resolved to minutes after midnight for the given day, sorted.
+    pub fn resolve(&self, sun: &SunTimes) -> Vec<(f64, u32, u8)> {
+        let mut resolved: Vec<_> = self
+            .points
+            .iter()
+            .map(|p| {
+                let base = match p.anchor {
+                    Anchor::Sunrise => sun.sunrise,
+                    Anchor::Noon => sun.noon,
+                    Anchor::Sunset => sun.sunset,
+                    Anchor::Clock(minutes) => minutes,
+                };
+                (
+                    (base + p.shift).rem_euclid(MINUTES_PER_DAY),
+                    p.kelvin,
+                    p.brightness,
+                )
+            })
+            .collect();
+        resolved.sort_by(|a, b| a.0.total_cmp(&b.0));
+        resolved
+    }
+
+    /// Kelvin and brightness at `minute` after local midnight.
+    pub fn at(&self, sun: &SunTimes, minute: f64) -> (u32, u8) {
+        let points = self.resolve(sun);
+        let next = points.iter().position(|p| p.0 > minute);
+        // Wrap around midnight at either end of the day
+        let (first, last) = (points[0], points[points.len() - 1]);
+        let (before, after) = match next {
+            Some(0) => ((last.0 - MINUTES_PER_DAY, last.1, last.2), first),
+            None => (last, (first.0 + MINUTES_PER_DAY, first.1, first.2)),
+            Some(i) => (points[i - 1], points[i]),
+        };
+        let span = after.0 - before.0;
+        let t = if span > 0.0 {
+            ((minute - before.0) / span).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let lerp = |a: f64, b: f64| a + (b - a) * t;
+        (
+            lerp(before.1 as f64, after.1 as f64).round() as u32,
+            lerp(before.2 as f64, after.2 as f64).round() as u8,
+        )
+    }
+}
+
+fn minute_of_day(now: OffsetDateTime) -> f64 {
+    now.hour() as f64 * 60.0 + now.minute() as f64 + now.second() as f64 / 60.0
+}
+
+fn clock_time(minute: f64) -> String {
+    let minute = minute.rem_euclid(MINUTES_PER_DAY).round() as u32;
+    format!("{:02}:{:02}", minute / 60 % 24, minute % 60)
+}
+
+/// Prints today's sun times and resolved curve.
+pub fn print_day(curve: &Curve, latitude: f64, longitude: f64) {
+    let now = clock::now();
+    let sun = sun_times(now.date(), now.offset(), latitude, longitude);
+    println!(
+        "Sunrise {} | Solar noon {} | Sunset {}",
+        clock_time(sun.sunrise),
+        clock_time(sun.noon),
+        clock_time(sun.sunset)
+    );
+    for (minute, kelvin, brightness) in curve.resolve(&sun) {
+        println!(
+            "  {}  {kelvin}K  brightness {brightness}",
+            clock_time(minute)
+        );
+    }
+}
+
+/// Follows the curve until Ctrl-C, updating the strip every `interval`.
+/// The device is connected only while writing, so other commands can use it
+/// in between; any change they record in the state file pauses updates for
+/// `resume_after`.
+pub async fn run(
+    curve: &Curve,
+    (latitude, longitude): (f64, f64),
+    device: &DeviceConfig,
+    interval: Duration,
+    resume_after: Duration,
+    transition: Duration,
+    once: bool,
+) -> Result<(), String> {
+    let mut ticker = tokio::time::interval(interval.max(Duration::from_secs(1)));
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    let mut written: Option<DeviceState> = None;
+    let mut paused_until: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            _ = ticker.tick() => {}
+            _ = &mut ctrl_c => break,
+        }
+
+        'update: {
+            let mut state = State::load()?;
+            let current = state.device(&device.mac);
+            if written.is_some_and(|w| w != current) {
+                println!(
+                    "Manual change detected, pausing for {} minutes",
+                    resume_after.as_secs() / 60
+                );
+                paused_until = Some(Instant::now() + resume_after);
+                written = None;
+            }
+            if paused_until.is_some_and(|until| Instant::now() < until) || !current.power {
+                break 'update;
+            }
+            paused_until = None;
+
+            let now = clock::now();
+            let sun = sun_times(now.date(), now.offset(), latitude, longitude);
+            let (kelvin, brightness) = curve.at(&sun, minute_of_day(now));
+            let target = DeviceState {
+                color: kelvin_to_color(kelvin),
+                brightness,
+                mode: Mode::Color,
+                ..current
+            };
+            if written == Some(target) {
+                break 'update;
+            }
+
+            let bluetooth = match BluetoothConnection::new(
+                device.mac.clone(),
+                crate::CHARACTERISTIC_UUID.to_string(),
+            )
+            .await
+            {
+                Ok(bluetooth) => bluetooth.calibrated(device.calibration),
+                Err(e) if once => return Err(e),
+                Err(e) => {
+                    // Another client may hold the connection; try again next time
+                    eprintln!("{e}");
+                    break 'update;
+                }
+            };
+            let faded = crate::animation::transition(
+                &bluetooth,
+                current.showing().unwrap_or(target.output()),
+                target.output(),
+                transition,
+            )
+            .await;
+            bluetooth.bye().await?;
+            let shown = faded?;
+            if shown != target.output() {
+                // Ctrl-C stopped the fade; record what the strip is left showing
+                let stopped = DeviceState {
+                    color: shown,
+                    brightness: 255,
+                    ..target
+                };
+                state.set_device(&device.mac, stopped);
+                state.save()?;
+                break 'update;
+            }
+            println!(
+                "{} {kelvin}K brightness {brightness}",
+                clock_time(minute_of_day(now))
+            );
+
+            state.set_device(&device.mac, target);
+            state.save()?;
+            written = Some(target);
+        }
+        if once {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use time::Month;
+
+    use super::*;
+
+    fn hours(h: i8) -> UtcOffset {
+        UtcOffset::from_hms(h, 0, 0).unwrap()
+    }
+
+    fn assert_near(minute: f64, expected: &str) {
+        let (h, m) = expected.split_once(':').unwrap();
+        let expected = h.parse::<f64>().unwrap() * 60.0 + m.parse::<f64>().unwrap();
+        assert!(
+            (minute - expected).abs() <= 2.0,
+            "{} is not near {expected:?}",
+            clock_time(minute)
+        );
+    }
+
+    #[test]
+    fn sun_times_match_noaa() {
+        // London on the summer solstice, in BST
+        let date = Date::from_calendar_date(2024, Month::June, 21).unwrap();
+        let sun = sun_times(date, hours(1), 51.5074, -0.1278);
+        assert_near(sun.sunrise, "04:43");
+        assert_near(sun.noon, "13:02");
+        assert_near(sun.sunset, "21:21");
+
+        // New York on the winter solstice, in EST
+        let date = Date::from_calendar_date(2024, Month::December, 21).unwrap();
+        let sun = sun_times(date, hours(-5), 40.7128, -74.0060);
+        assert_near(sun.sunrise, "07:16");
+        assert_near(sun.noon, "11:54");
+        assert_near(sun.sunset, "16:32");
+    }
+
+    #[test]
+    fn polar_days_collapse_around_noon() {
+        let tromso = (69.6496, 18.9560);
+        let june = Date::from_calendar_date(2024, Month::June, 21).unwrap();
+        let sun = sun_times(june, hours(2), tromso.0, tromso.1);
+        assert_eq!(sun.sunset - sun.sunrise, MINUTES_PER_DAY);
+        let december = Date::from_calendar_date(2024, Month::December, 21).unwrap();
+        let sun = sun_times(december, hours(1), tromso.0, tromso.1);
+        assert_eq!(sun.sunrise, sun.noon);
+        assert_eq!(sun.sunset, sun.noon);
+    }
+
+    #[test]
+    fn parses_point_times() {
+        assert!(matches!(parse_at("sunset-1h"), Ok((Anchor::Sunset, -60.0))));
+        assert!(matches!(
+            parse_at("Sunrise+30m"),
+            Ok((Anchor::Sunrise, 30.0))
+        ));
+        assert!(matches!(parse_at("noon"), Ok((Anchor::Noon, 0.0))));
+        assert!(matches!(parse_at("22:30"), Ok((Anchor::Clock(m), 0.0)) if m == 1350.0));
+        assert!(matches!(parse_at("06:00-15m"), Ok((Anchor::Clock(m), -15.0)) if m == 360.0));
+        for invalid in ["25:00", "12:60", "dusk", "sunset+soon", "7"] {
+            assert!(parse_at(invalid).is_err(), "{invalid}");
+        }
+    }
+
+    fn curve() -> Curve {
+        let point = |at: &str, kelvin, brightness| Point {
+            at: at.to_string(),
+            kelvin,
+            brightness,
+        };
+        Curve::parse(&[
+            point("12:00", 5000, 200),
+            point("06:00", 2000, 0),
+            point("sunset+1h", 3000, 100),
+        ])
+        .unwrap()
+    }
+
+    const SUN: SunTimes = SunTimes {
+        sunrise: 360.0,
+        noon: 780.0,
+        sunset: 1140.0,
+    };
+
+    #[test]
+    fn curve_resolves_and_sorts_points() {
+        let resolved = curve().resolve(&SUN);
+        assert_eq!(
+            resolved,
+            vec![(360.0, 2000, 0), (720.0, 5000, 200), (1200.0, 3000, 100)]
+        );
+    }
+
+    #[test]
+    fn curve_interpolates_between_points() {
+        let curve = curve();
+        assert_eq!(curve.at(&SUN, 360.0), (2000, 0));
+        assert_eq!(curve.at(&SUN, 540.0), (3500, 100));
+        assert_eq!(curve.at(&SUN, 720.0), (5000, 200));
+        assert_eq!(curve.at(&SUN, 960.0), (4000, 150));
+    }
+
+    #[test]
+    fn curve_wraps_around_midnight() {
+        let curve = curve();
+        // 20:00 to 06:00 is one ten hour segment, on either side of midnight
+        assert_eq!(curve.at(&SUN, 1380.0), (2700, 70));
+        assert_eq!(curve.at(&SUN, 0.0), (2600, 60));
+        assert_eq!(curve.at(&SUN, 120.0), (2400, 40));
+        // No jump at midnight itself
+        assert_eq!(curve.at(&SUN, 1439.99), curve.at(&SUN, 0.0));
+    }
+
+    #[test]
+    fn rejects_out_of_range_temperatures() {
+        let point = Point {
+            at: "noon".to_string(),
+            kelvin: 100,
+            brightness: 255,
+        };
+        assert!(Curve::parse(&[point]).is_err());
+        assert!(Curve::parse(&[]).is_ok());
+    }
+}
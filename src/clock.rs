@@ -0,0 +1,83 @@
+use time::{OffsetDateTime, Time, UtcOffset};
+
+/// The current local date and time. The UTC offset comes from the system's
+/// time zone rules for this instant, so long-running modes follow DST changes.
+pub fn now() -> OffsetDateTime {
+    let utc = OffsetDateTime::now_utc();
+    utc.to_offset(UtcOffset::local_offset_at(utc).unwrap_or(UtcOffset::UTC))
+}
+
+/// Parses a 24-hour `HH:MM` time of day.
+pub fn parse_time(s: &str) -> Result<Time, String> {
+    let invalid = || format!("invalid time '{s}', expected HH:MM");
+    let (h, m) = s.trim().split_once(':').ok_or_else(invalid)?;
+    let (h, m) = (
+        h.parse().map_err(|_| invalid())?,
+        m.parse().map_err(|_| invalid())?,
+    );
+    Time::from_hms(h, m, 0).map_err(|_| invalid())
+}
+
+/// The first occurrence of `time` strictly after `after`.
+pub fn next_at(time: Time, after: OffsetDateTime) -> OffsetDateTime {
+    let today = after.replace_time(time);
+    if today > after {
+        today
+    } else {
+        today + time::Duration::DAY
+    }
+}
+
+/// Source of the current time, so schedules can be driven by a fake clock.
+pub trait Clock {
+    fn now(&self) -> OffsetDateTime;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> OffsetDateTime {
+        now()
+    }
+}
+
+/// Formats a time as e.g. `Mon 2026-10-19 07:00`.
+pub fn format(t: OffsetDateTime) -> String {
+    let weekday = t.weekday().to_string();
+    format!(
+        "{} {} {:02}:{:02}",
+        &weekday[..3],
+        t.date(),
+        t.hour(),
+        t.minute()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month};
+
+    use super::*;
+
+    #[test]
+    fn parses_times() {
+        assert_eq!(
+            parse_time(" 7:05 "),
+            Time::from_hms(7, 5, 0).map_err(|e| e.to_string())
+        );
+        assert!(parse_time("24:00").is_err());
+        assert!(parse_time("7").is_err());
+        assert!(parse_time("7:xx").is_err());
+    }
+
+    #[test]
+    fn next_at_rolls_over_to_tomorrow() {
+        let date = Date::from_calendar_date(2026, Month::October, 16).unwrap();
+        let seven = Time::from_hms(7, 0, 0).unwrap();
+        let morning = date.with_hms(6, 0, 0).unwrap().assume_utc();
+        assert_eq!(next_at(seven, morning), date.with_time(seven).assume_utc());
+        // Strictly after, so the current minute is tomorrow's
+        let at = date.with_time(seven).assume_utc();
+        assert_eq!(next_at(seven, at), at + time::Duration::DAY);
+    }
+}
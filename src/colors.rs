@@ -0,0 +1,547 @@
+use std::str::FromStr;
+
+use crate::controller::Color;
+
+const SYNTAX_HELP: &str = "expected #rrggbb, rgb(r,g,b), hsl(h,s%,l%), hsv(h,s%,v%), \
+     'r g b', a color name like goldenrod, or a temperature like 3000K";
+
+/// Lowest and highest color temperatures accepted, in Kelvin.
+pub const KELVIN_RANGE: (u32, u32) = (1000, 40000);
+
+/// CSS Color Module Level 4 named colors (the X11 set plus rebeccapurple).
+const NAMED: &[(&str, [u8; 3])] = &[
+    ("aliceblue", [240, 248, 255]),
+    ("antiquewhite", [250, 235, 215]),
+    ("aqua", [0, 255, 255]),
+    ("aquamarine", [127, 255, 212]),
+    ("azure", [240, 255, 255]),
+    ("beige", [245, 245, 220]),
+    ("bisque", [255, 228, 196]),
+    ("black", [0, 0, 0]),
+    ("blanchedalmond", [255, 235, 205]),
+    ("blue", [0, 0, 255]),
+    ("blueviolet", [138, 43, 226]),
+    ("brown", [165, 42, 42]),
+    ("burlywood", [222, 184, 135]),
+    ("cadetblue", [95, 158, 160]),
+    ("chartreuse", [127, 255, 0]),
+    ("chocolate", [210, 105, 30]),
+    ("coral", [255, 127, 80]),
+    ("cornflowerblue", [100, 149, 237]),
+    ("cornsilk", [255, 248, 220]),
+    ("crimson", [220, 20, 60]),
+    ("cyan", [0, 255, 255]),
+    ("darkblue", [0, 0, 139]),
+    ("darkcyan", [0, 139, 139]),
+    ("darkgoldenrod", [184, 134, 11]),
+    ("darkgray", [169, 169, 169]),
+    ("darkgreen", [0, 100, 0]),
+    ("darkgrey", [169, 169, 169]),
+    ("darkkhaki", [189, 183, 107]),
+    ("darkmagenta", [139, 0, 139]),
+    ("darkolivegreen", [85, 107, 47]),
+    ("darkorange", [255, 140, 0]),
+    ("darkorchid", [153, 50, 204]),
+    ("darkred", [139, 0, 0]),
+    ("darksalmon", [233, 150, 122]),
+    ("darkseagreen", [143, 188, 143]),
+    ("darkslateblue", [72, 61, 139]),
+    ("darkslategray", [47, 79, 79]),
+    ("darkslategrey", [47, 79, 79]),
+    ("darkturquoise", [0, 206, 209]),
+    ("darkviolet", [148, 0, 211]),
+    ("deeppink", [255, 20, 147]),
+    ("deepskyblue", [0, 191, 255]),
+    ("dimgray", [105, 105, 105]),
+    ("dimgrey", [105, 105, 105]),
+    ("dodgerblue", [30, 144, 255]),
+    ("firebrick", [178, 34, 34]),
+    ("floralwhite", [255, 250, 240]),
+    ("forestgreen", [34, 139, 34]),
+    ("fuchsia", [255, 0, 255]),
+    ("gainsboro", [220, 220, 220]),
+    ("ghostwhite", [248, 248, 255]),
+    ("gold", [255, 215, 0]),
+    ("goldenrod", [218, 165, 32]),
+    ("gray", [128, 128, 128]),
+    ("green", [0, 128, 0]),
+    ("greenyellow", [173, 255, 47]),
+    ("grey", [128, 128, 128]),
+    ("honeydew", [240, 255, 240]),
+    ("hotpink", [255, 105, 180]),
+    ("indianred", [205, 92, 92]),
+    ("indigo", [75, 0, 130]),
+    ("ivory", [255, 255, 240]),
+    ("khaki", [240, 230, 140]),
+    ("lavender", [230, 230, 250]),
+    ("lavenderblush", [255, 240, 245]),
+    ("lawngreen", [124, 252, 0]),
+    ("lemonchiffon", [255, 250, 205]),
+    ("lightblue", [173, 216, 230]),
+    ("lightcoral", [240, 128, 128]),
+    ("lightcyan", [224, 255, 255]),
+    ("lightgoldenrodyellow", [250, 250, 210]),
+    ("lightgray", [211, 211, 211]),
+    ("lightgreen", [144, 238, 144]),
+    ("lightgrey", [211, 211, 211]),
+    ("lightpink", [255, 182, 193]),
+    ("lightsalmon", [255, 160, 122]),
+    ("lightseagreen", [32, 178, 170]),
+    ("lightskyblue", [135, 206, 250]),
+    ("lightslategray", [119, 136, 153]),
+    ("lightslategrey", [119, 136, 153]),
+    ("lightsteelblue", [176, 196, 222]),
+    ("lightyellow", [255, 255, 224]),
+    ("lime", [0, 255, 0]),
+    ("limegreen", [50, 205, 50]),
+    ("linen", [250, 240, 230]),
+    ("magenta", [255, 0, 255]),
+    ("maroon", [128, 0, 0]),
+    ("mediumaquamarine", [102, 205, 170]),
+    ("mediumblue", [0, 0, 205]),
+    ("mediumorchid", [186, 85, 211]),
+    ("mediumpurple", [147, 112, 219]),
+    ("mediumseagreen", [60, 179, 113]),
+    ("mediumslateblue", [123, 104, 238]),
+    ("mediumspringgreen", [0, 250, 154]),
+    ("mediumturquoise", [72, 209, 204]),
+    ("mediumvioletred", [199, 21, 133]),
+    ("midnightblue", [25, 25, 112]),
+    ("mintcream", [245, 255, 250]),
+    ("mistyrose", [255, 228, 225]),
+    ("moccasin", [255, 228, 181]),
+    ("navajowhite", [255, 222, 173]),
+    ("navy", [0, 0, 128]),
+    ("oldlace", [253, 245, 230]),
+    ("olive", [128, 128, 0]),
+    ("olivedrab", [107, 142, 35]),
+    ("orange", [255, 165, 0]),
+    ("orangered", [255, 69, 0]),
+    ("orchid", [218, 112, 214]),
+    ("palegoldenrod", [238, 232, 170]),
+    ("palegreen", [152, 251, 152]),
+    ("paleturquoise", [175, 238, 238]),
+    ("palevioletred", [219, 112, 147]),
+    ("papayawhip", [255, 239, 213]),
+    ("peachpuff", [255, 218, 185]),
+    ("peru", [205, 133, 63]),
+    ("pink", [255, 192, 203]),
+    ("plum", [221, 160, 221]),
+    ("powderblue", [176, 224, 230]),
+    ("purple", [128, 0, 128]),
+    ("rebeccapurple", [102, 51, 153]),
+    ("red", [255, 0, 0]),
+    ("rosybrown", [188, 143, 143]),
+    ("royalblue", [65, 105, 225]),
+    ("saddlebrown", [139, 69, 19]),
+    ("salmon", [250, 128, 114]),
+    ("sandybrown", [244, 164, 96]),
+    ("seagreen", [46, 139, 87]),
+    ("seashell", [255, 245, 238]),
+    ("sienna", [160, 82, 45]),
+    ("silver", [192, 192, 192]),
+    ("skyblue", [135, 206, 235]),
+    ("slateblue", [106, 90, 205]),
+    ("slategray", [112, 128, 144]),
+    ("slategrey", [112, 128, 144]),
+    ("snow", [255, 250, 250]),
+    ("springgreen", [0, 255, 127]),
+    ("steelblue", [70, 130, 180]),
+    ("tan", [210, 180, 140]),
+    ("teal", [0, 128, 128]),
+    ("thistle", [216, 191, 216]),
+    ("tomato", [255, 99, 71]),
+    ("turquoise", [64, 224, 208]),
+    ("violet", [238, 130, 238]),
+    ("wheat", [245, 222, 179]),
+    ("white", [255, 255, 255]),
+    ("whitesmoke", [245, 245, 245]),
+    ("yellow", [255, 255, 0]),
+    ("yellowgreen", [154, 205, 50]),
+];
+
+/// Approximates the color of a black body at `kelvin` (Tanner Helland's fit).
+pub fn kelvin_to_color(kelvin: u32) -> Color {
+    let t = kelvin.clamp(KELVIN_RANGE.0, KELVIN_RANGE.1) as f64 / 100.0;
+    let r = if t <= 66.0 {
+        255.0
+    } else {
+        329.698727446 * (t - 60.0).powf(-0.1332047592)
+    };
+    let g = if t <= 66.0 {
+        99.4708025861 * t.ln() - 161.1195681661
+    } else {
+        288.1221695283 * (t - 60.0).powf(-0.0755148492)
+    };
+    let b = if t >= 66.0 {
+        255.0
+    } else if t <= 19.0 {
+        0.0
+    } else {
+        138.5177312231 * (t - 10.0).ln() - 305.0447927307
+    };
+    let channel = |v: f64| v.round().clamp(0.0, 255.0) as u8;
+    Color {
+        r: channel(r),
+        g: channel(g),
+        b: channel(b),
+    }
+}
+
+/// Parses a color temperature such as `2700` or `2700K`.
+pub fn parse_kelvin(s: &str) -> Result<u32, String> {
+    let digits = s.trim().trim_end_matches(['k', 'K']).trim();
+    let kelvin = digits
+        .parse::<u32>()
+        .map_err(|_| format!("invalid temperature '{s}', expected e.g. 2700K"))?;
+    if !(KELVIN_RANGE.0..=KELVIN_RANGE.1).contains(&kelvin) {
+        return Err(format!(
+            "temperature must be between {}K and {}K",
+            KELVIN_RANGE.0, KELVIN_RANGE.1
+        ));
+    }
+    Ok(kelvin)
+}
+
+fn hsl_to_color(h: f64, s: f64, l: f64) -> Color {
+    // HSL and HSV share hue; convert lightness to value
+    let v = l + s * l.min(1.0 - l);
+    let sv = if v == 0.0 { 0.0 } else { 2.0 * (1.0 - l / v) };
+    Color::from_hsv(h, sv, v)
+}
+
+/// Splits `name(a, b, c)` into its comma- or space-separated arguments.
+fn function_args<'a>(s: &'a str, name: &str) -> Option<Vec<&'a str>> {
+    let inner = s.strip_prefix(name)?.trim_start().strip_prefix('(')?;
+    let inner = inner.strip_suffix(')')?;
+    Some(
+        inner
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|a| !a.is_empty())
+            .collect(),
+    )
+}
+
+fn number(arg: &str, what: &str) -> Result<f64, String> {
+    arg.trim_end_matches('%')
+        .trim_end_matches("deg")
+        .parse::<f64>()
+        .map_err(|_| format!("invalid {what} '{arg}'"))
+}
+
+fn channel(arg: &str) -> Result<u8, String> {
+    let value = number(arg, "channel")?;
+    let value = if arg.ends_with('%') {
+        value * 255.0 / 100.0
+    } else {
+        value
+    };
+    if !(0.0..=255.0).contains(&value) {
+        return Err(format!("channel '{arg}' is out of range 0-255"));
+    }
+    Ok(value.round() as u8)
+}
+
+fn percent(arg: &str, what: &str) -> Result<f64, String> {
+    let value = number(arg, what)?;
+    if !(0.0..=100.0).contains(&value) {
+        return Err(format!("{what} '{arg}' is out of range 0-100%"));
+    }
+    Ok(value / 100.0)
+}
+
+fn three<'a>(args: &'a [&'a str], name: &str) -> Result<[&'a str; 3], String> {
+    match args {
+        [a, b, c] => Ok([a, b, c]),
+        _ => Err(format!("{name}() takes 3 values, got {}", args.len())),
+    }
+}
+
+/// Levenshtein distance, used to suggest color names.
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let above = row[j + 1];
+            row[j + 1] = (diagonal + usize::from(ca != *cb))
+                .min(row[j] + 1)
+                .min(above + 1);
+            diagonal = above;
+        }
+    }
+    row[b.len()]
+}
+
+impl FromStr for Color {
+    type Err = String;
+
+    fn from_str(input: &str) -> Result<Color, String> {
+        let s = input.trim().to_ascii_lowercase();
+        let invalid = |reason: String| format!("invalid color '{}': {reason}", input.trim());
+
+        if s.is_empty() {
+            return Err(invalid(SYNTAX_HELP.to_string()));
+        }
+
+        if let Some(hex) = s.strip_prefix('#').or_else(|| s.strip_prefix("0x")) {
+            let hex = match hex.len() {
+                3 => hex.chars().flat_map(|c| [c, c]).collect(),
+                6 => hex.to_string(),
+                _ => return Err(invalid("hex colors need 3 or 6 digits".to_string())),
+            };
+            return Color::from_hex(&hex).ok_or_else(|| invalid("not a hex number".to_string()));
+        }
+
+        if let Some(args) = function_args(&s, "rgb") {
+            let [r, g, b] = three(&args, "rgb").map_err(invalid)?;
+            return Ok(Color {
+                r: channel(r).map_err(invalid)?,
+                g: channel(g).map_err(invalid)?,
+                b: channel(b).map_err(invalid)?,
+            });
+        }
+
+        if let Some(args) = function_args(&s, "hsl") {
+            let [h, sat, l] = three(&args, "hsl").map_err(invalid)?;
+            return Ok(hsl_to_color(
+                number(h, "hue").map_err(invalid)?,
+                percent(sat, "saturation").map_err(invalid)?,
+                percent(l, "lightness").map_err(invalid)?,
+            ));
+        }
+
+        if let Some(args) = function_args(&s, "hsv").or_else(|| function_args(&s, "hsb")) {
+            let [h, sat, v] = three(&args, "hsv").map_err(invalid)?;
+            return Ok(Color::from_hsv(
+                number(h, "hue").map_err(invalid)?,
+                percent(sat, "saturation").map_err(invalid)?,
+                percent(v, "value").map_err(invalid)?,
+            ));
+        }
+
+        if let Some(kelvin) = s.strip_suffix('k')
+            && kelvin.trim().parse::<u32>().is_ok()
+        {
+            return parse_kelvin(&s).map(kelvin_to_color).map_err(invalid);
+        }
+
+        let parts: Vec<&str> = s
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|p| !p.is_empty())
+            .collect();
+        if parts.len() == 3 && parts.iter().all(|p| p.parse::<f64>().is_ok()) {
+            return Ok(Color {
+                r: channel(parts[0]).map_err(invalid)?,
+                g: channel(parts[1]).map_err(invalid)?,
+                b: channel(parts[2]).map_err(invalid)?,
+            });
+        }
+
+        let name: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+        if let Some((_, [r, g, b])) = NAMED.iter().find(|(n, _)| *n == name) {
+            return Ok(Color {
+                r: *r,
+                g: *g,
+                b: *b,
+            });
+        }
+        if name.len() == 6 && name.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Color::from_hex(&name).ok_or_else(|| invalid("not a hex number".to_string()));
+        }
+        if name.chars().all(|c| c.is_ascii_alphabetic()) {
+            let (closest, d) = NAMED
+                .iter()
+                .map(|(n, _)| (*n, distance(&name, n)))
+                .min_by_key(|(_, d)| *d)
+                .unwrap_or(("", usize::MAX));
+            if d <= 2 {
+                return Err(invalid(format!(
+                    "unknown color name, did you mean '{closest}'?"
+                )));
+            }
+            return Err(invalid("unknown color name".to_string()));
+        }
+        Err(invalid(SYNTAX_HELP.to_string()))
+    }
+}
+
+/// Parses a color given as one or more command-line words, so both
+/// `color 255 136 0` and `color "#ff8800"` work.
+pub fn parse_args(words: &[String]) -> Result<Color, String> {
+    words.join(" ").parse()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ORANGE: Color = Color {
+        r: 255,
+        g: 136,
+        b: 0,
+    };
+
+    fn parse(s: &str) -> Result<Color, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn parses_hex() {
+        for s in [
+            "#ff8800",
+            "#FF8800",
+            "#f80",
+            "0xff8800",
+            "ff8800",
+            "  #ff8800 ",
+        ] {
+            assert_eq!(parse(s), Ok(ORANGE), "{s}");
+        }
+        let samples = [
+            ORANGE,
+            Color { r: 0, g: 0, b: 0 },
+            Color {
+                r: 1,
+                g: 254,
+                b: 127,
+            },
+        ];
+        for color in samples {
+            assert_eq!(parse(&color.to_string()), Ok(color));
+        }
+    }
+
+    #[test]
+    fn parses_channels() {
+        for s in [
+            "255 136 0",
+            "255,136,0",
+            "255, 136, 0",
+            "rgb(255, 136, 0)",
+            "RGB(255 136 0)",
+        ] {
+            assert_eq!(parse(s), Ok(ORANGE), "{s}");
+        }
+        assert_eq!(
+            parse("rgb(100%, 0, 50%)"),
+            Ok(Color {
+                r: 255,
+                g: 0,
+                b: 128
+            })
+        );
+        let words = ["255", "136", "0"].map(String::from);
+        assert_eq!(parse_args(&words), Ok(ORANGE));
+        assert_eq!(parse_args(&["#ff8800".to_string()]), Ok(ORANGE));
+    }
+
+    #[test]
+    fn parses_every_name() {
+        for (name, [r, g, b]) in NAMED {
+            assert_eq!(
+                parse(name),
+                Ok(Color {
+                    r: *r,
+                    g: *g,
+                    b: *b
+                }),
+                "{name}"
+            );
+        }
+        assert_eq!(parse("Golden Rod"), parse("goldenrod"));
+    }
+
+    #[test]
+    fn parses_hsl_and_hsv() {
+        let red = Color { r: 255, g: 0, b: 0 };
+        assert_eq!(parse("hsl(0, 100%, 50%)"), Ok(red));
+        assert_eq!(
+            parse("hsl(240deg 100% 50%)"),
+            Ok(Color { r: 0, g: 0, b: 255 })
+        );
+        assert_eq!(
+            parse("hsl(0, 0%, 100%)"),
+            Ok(Color {
+                r: 255,
+                g: 255,
+                b: 255
+            })
+        );
+        assert_eq!(parse("hsv(0, 100%, 100%)"), Ok(red));
+        assert_eq!(parse("hsb(360, 100%, 100%)"), Ok(red));
+
+        let samples = [
+            ORANGE,
+            Color {
+                r: 12,
+                g: 200,
+                b: 99,
+            },
+            Color {
+                r: 128,
+                g: 128,
+                b: 128,
+            },
+        ];
+        for color in samples {
+            let (h, s, v) = color.to_hsv();
+            let text = format!("hsv({h:.3}, {:.3}%, {:.3}%)", s * 100.0, v * 100.0);
+            assert_eq!(parse(&text), Ok(color), "{text}");
+        }
+    }
+
+    #[test]
+    fn parses_temperatures() {
+        assert_eq!(parse("2700K"), Ok(kelvin_to_color(2700)));
+        assert_eq!(parse("6500k"), Ok(kelvin_to_color(6500)));
+        assert_eq!(parse_kelvin("2700"), Ok(2700));
+        assert_eq!(parse_kelvin(" 4000 K "), Ok(4000));
+        assert_eq!(
+            parse("500K"),
+            Err("invalid color '500K': temperature must be between 1000K and 40000K".to_string())
+        );
+        assert_eq!(
+            parse_kelvin("warm"),
+            Err("invalid temperature 'warm', expected e.g. 2700K".to_string())
+        );
+    }
+
+    #[test]
+    fn explains_errors() {
+        let error = |s: &str| parse(s).unwrap_err();
+        assert_eq!(
+            error("#12345"),
+            "invalid color '#12345': hex colors need 3 or 6 digits"
+        );
+        assert_eq!(error("#ggg"), "invalid color '#ggg': not a hex number");
+        assert_eq!(
+            error("rgb(1, 2)"),
+            "invalid color 'rgb(1, 2)': rgb() takes 3 values, got 2"
+        );
+        assert_eq!(
+            error("rgb(256, 0, 0)"),
+            "invalid color 'rgb(256, 0, 0)': channel '256' is out of range 0-255"
+        );
+        assert_eq!(
+            error("hsl(0, 150%, 50%)"),
+            "invalid color 'hsl(0, 150%, 50%)': saturation '150%' is out of range 0-100%"
+        );
+        assert_eq!(
+            error("hsv(red, 1%, 1%)"),
+            "invalid color 'hsv(red, 1%, 1%)': invalid hue 'red'"
+        );
+        assert_eq!(
+            error("Goldenrot"),
+            "invalid color 'Goldenrot': unknown color name, did you mean 'goldenrod'?"
+        );
+        assert_eq!(
+            error("zzzzzzzz"),
+            "invalid color 'zzzzzzzz': unknown color name"
+        );
+        assert_eq!(error(""), format!("invalid color '': {SYNTAX_HELP}"));
+        assert_eq!(error("1 2"), format!("invalid color '1 2': {SYNTAX_HELP}"));
+    }
+}
@@ -0,0 +1,115 @@
+use std::{collections::BTreeMap, fs, path::PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{calibration::Calibration, circadian, keymap};
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub devices: Vec<DeviceConfig>,
+    /// Default fade time for color and brightness commands, in milliseconds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transition_ms: Option<u64>,
+    /// Most frames per second the TUI writes to the strip.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_fps: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circadian: Option<circadian::Settings>,
+    /// Timed actions such as `weekdays 07:00 color 255 180 100`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub schedules: Vec<String>,
+    /// Send the restored session to the strip when the TUI starts.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub sync_on_start: bool,
+    /// TUI key bindings by action, such as `quit = "q"` or `up = ["up", "k"]`.
+    /// Favorites are on Alt+1-9 since the digits select a channel; binding
+    /// `apply_favorite_1 = "1"` and so on puts them on the plain digits.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub keys: BTreeMap<String, keymap::Keys>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub steps: Option<keymap::Steps>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeviceConfig {
+    pub name: String,
+    pub mac: String,
+    /// First DMX channel (1-based) of this device's footprint.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dmx_start: Option<u16>,
+    #[serde(default, skip_serializing_if = "Calibration::is_identity")]
+    pub calibration: Calibration,
+}
+
+impl Config {
+    pub fn dir() -> Option<PathBuf> {
+        dirs::config_dir().map(|d| d.join("batlights"))
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        Self::dir().map(|d| d.join("config.toml"))
+    }
+
+    /// Loads the config file, falling back to defaults when it does not exist.
+    pub fn load() -> Result<Config, String> {
+        let Some(path) = Self::path() else {
+            return Ok(Config::default());
+        };
+        let config: Config = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text)
+                .map_err(|e| format!("Config Error: {}: {e}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
+            Err(e) => return Err(format!("Config Error: {}: {e}", path.display())),
+        };
+        for device in &config.devices {
+            device.calibration.validate().map_err(|e| {
+                format!(
+                    "Config Error: {}: device '{}': {e}",
+                    path.display(),
+                    device.name
+                )
+            })?;
+        }
+        Ok(config)
+    }
+
+    /// Configured devices, or the built-in strip when none are configured.
+    pub fn devices(&self) -> Vec<DeviceConfig> {
+        if self.devices.is_empty() {
+            vec![DeviceConfig {
+                name: "default".to_string(),
+                mac: crate::MAC_ADDR.to_string(),
+                dmx_start: None,
+                calibration: Calibration::default(),
+            }]
+        } else {
+            self.devices.clone()
+        }
+    }
+
+    pub fn default_device(&self) -> DeviceConfig {
+        self.devices().remove(0)
+    }
+
+    /// Looks a device up by name or MAC address.
+    pub fn device(&self, name: &str) -> Result<DeviceConfig, String> {
+        self.devices()
+            .into_iter()
+            .find(|d| d.name == name || d.mac.eq_ignore_ascii_case(name))
+            .ok_or(format!("Config Error: no device named '{name}'"))
+    }
+
+    /// Writes the config back, creating the directory if needed. Comments in
+    /// a hand-written file are not preserved.
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let path = Self::path().ok_or("Config Error: No config directory available")?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Config Error: {e}"))?;
+        }
+        let text = toml::to_string_pretty(self).map_err(|e| format!("Config Error: {e}"))?;
+        fs::write(&path, text).map_err(|e| format!("Config Error: {}: {e}", path.display()))?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,357 @@
+use std::{path::PathBuf, sync::Arc};
+
+use dbus::{
+    arg::{RefArg, Variant},
+    blocking::stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged,
+    channel::MatchingReceiver,
+    message::{MatchRule, SignalArgs},
+    nonblock::SyncConnection,
+};
+use dbus_crossroads::{Context, Crossroads, MethodErr};
+use dbus_tokio::connection::IOResource;
+use tokio::sync::mpsc;
+
+use crate::{
+    controller::{Color, Controller},
+    state::{DeviceState, Mode, State},
+};
+
+pub const BUS_NAME: &str = "org.batlights.Lights";
+const OBJECT_PATH: &str = "/org/batlights/Lights";
+const INTERFACE: &str = "org.batlights.Lights";
+
+/// State exported on the bus. The strip cannot be queried, so it starts from
+/// the state file and every change is written back, keeping other commands
+/// and circadian mode in step with what was set over the bus.
+pub struct Lights {
+    mac: String,
+    /// State file, or `None` when there is no state directory.
+    path: Option<PathBuf>,
+    device: DeviceState,
+    tx: mpsc::UnboundedSender<[u8; 9]>,
+}
+
+impl Lights {
+    /// Loads the last known state of `mac`; frames are forwarded to `tx`.
+    pub fn load(
+        mac: &str,
+        path: Option<PathBuf>,
+        tx: mpsc::UnboundedSender<[u8; 9]>,
+    ) -> Result<Lights, String> {
+        let state = match &path {
+            Some(path) => State::load_from(path)?,
+            None => State::default(),
+        };
+        Ok(Lights {
+            mac: mac.to_string(),
+            device: state.device(mac),
+            path,
+            tx,
+        })
+    }
+
+    /// Sends `payload`, then records `device` as the new state.
+    fn apply(&mut self, device: DeviceState, payload: [u8; 9]) -> Result<(), MethodErr> {
+        self.tx
+            .send(payload)
+            .map_err(|_| MethodErr::failed("Bluetooth connection closed"))?;
+        self.device = device;
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        // Reload first so changes other commands made meanwhile are kept
+        let mut state = State::load_from(path).map_err(|e| MethodErr::failed(&e))?;
+        state.set_device(&self.mac, device);
+        state.save_to(path).map_err(|e| MethodErr::failed(&e))
+    }
+
+    fn color(&self) -> (u8, u8, u8) {
+        let Color { r, g, b } = self.device.output();
+        (r, g, b)
+    }
+
+    fn set_power(&mut self, on: bool) -> Result<(), MethodErr> {
+        let device = DeviceState {
+            power: on,
+            ..self.device
+        };
+        self.apply(device, Controller::power(on))
+    }
+
+    fn set_color(&mut self, (r, g, b): (u8, u8, u8)) -> Result<(), MethodErr> {
+        let device = DeviceState {
+            color: Color { r, g, b },
+            brightness: 255,
+            mode: Mode::Color,
+            ..self.device
+        };
+        self.apply(device, Controller::color(device.color))
+    }
+
+    fn set_pattern(&mut self, index: u8) -> Result<(), MethodErr> {
+        let device = DeviceState {
+            pattern: index.min(210),
+            mode: Mode::Pattern,
+            ..self.device
+        };
+        self.apply(device, Controller::pattern(device.pattern))
+    }
+
+    fn set_mic(&mut self, sensitivity: u8) -> Result<(), MethodErr> {
+        let device = DeviceState {
+            mic: sensitivity,
+            mode: Mode::Mic,
+            ..self.device
+        };
+        self.apply(device, Controller::mic(sensitivity))
+    }
+}
+
+fn emit_changed(ctx: &mut Context, name: &str, value: Box<dyn RefArg>) {
+    let mut changed = PropertiesPropertiesChanged {
+        interface_name: INTERFACE.to_string(),
+        changed_properties: Default::default(),
+        invalidated_properties: vec![],
+    };
+    changed
+        .changed_properties
+        .insert(name.to_string(), Variant(value));
+    let msg = changed.to_emit_message(ctx.path());
+    ctx.push_msg(msg);
+}
+
+fn build(lights: Lights) -> Crossroads {
+    let mut cr = Crossroads::new();
+    let token = cr.register(INTERFACE, |b| {
+        b.method(
+            "SetPower",
+            ("on",),
+            (),
+            |ctx, lights: &mut Lights, (on,): (bool,)| {
+                lights.set_power(on)?;
+                emit_changed(ctx, "Power", Box::new(on));
+                Ok(())
+            },
+        );
+        b.method(
+            "SetColor",
+            ("r", "g", "b"),
+            (),
+            |ctx, lights: &mut Lights, rgb: (u8, u8, u8)| {
+                lights.set_color(rgb)?;
+                emit_changed(ctx, "Color", Box::new(rgb));
+                Ok(())
+            },
+        );
+        b.method(
+            "SetPattern",
+            ("index",),
+            (),
+            |ctx, lights: &mut Lights, (index,): (u8,)| {
+                lights.set_pattern(index)?;
+                emit_changed(ctx, "Pattern", Box::new(lights.device.pattern));
+                Ok(())
+            },
+        );
+        b.method(
+            "SetMic",
+            ("sensitivity",),
+            (),
+            |ctx, lights: &mut Lights, (sensitivity,): (u8,)| {
+                lights.set_mic(sensitivity)?;
+                emit_changed(ctx, "Mic", Box::new(sensitivity));
+                Ok(())
+            },
+        );
+
+        b.property("Power")
+            .get(|_, lights: &mut Lights| Ok(lights.device.power))
+            .set(|_, lights, on| lights.set_power(on).map(|_| Some(on)));
+        b.property("Color")
+            .get(|_, lights: &mut Lights| Ok(lights.color()))
+            .set(|_, lights, rgb| lights.set_color(rgb).map(|_| Some(rgb)));
+        b.property("Pattern")
+            .get(|_, lights: &mut Lights| Ok(lights.device.pattern))
+            .set(|_, lights, index| {
+                lights
+                    .set_pattern(index)
+                    .map(|_| Some(lights.device.pattern))
+            });
+        b.property("Mic")
+            .get(|_, lights: &mut Lights| Ok(lights.device.mic))
+            .set(|_, lights, sensitivity| lights.set_mic(sensitivity).map(|_| Some(sensitivity)));
+    });
+    cr.insert(OBJECT_PATH, &[token], lights);
+    cr
+}
+
+/// Registers `org.batlights.Lights` on the session bus and serves it until
+/// Ctrl-C.
+pub async fn serve(lights: Lights) -> Result<(), String> {
+    let (resource, conn) =
+        dbus_tokio::connection::new_session_sync().map_err(|e| format!("D-Bus Error: {e}"))?;
+    serve_on(resource, conn, lights, async {
+        let _ = tokio::signal::ctrl_c().await;
+    })
+    .await
+}
+
+/// Serves the lights on an open connection until `shutdown` completes. Their
+/// sender is dropped before returning, which ends the Bluetooth task.
+async fn serve_on(
+    resource: IOResource<SyncConnection>,
+    conn: Arc<SyncConnection>,
+    lights: Lights,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), String> {
+    let mut resource = tokio::spawn(resource);
+
+    if let Err(e) = conn.request_name(BUS_NAME, false, true, true).await {
+        resource.abort();
+        return Err(format!("D-Bus Error: could not acquire {BUS_NAME}: {e}"));
+    }
+
+    let mut cr = build(lights);
+    let receiver = conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg, conn| {
+            let _ = cr.handle_message(msg, conn);
+            true
+        }),
+    );
+
+    let result = tokio::select! {
+        lost = &mut resource => Err(match lost {
+            Ok(e) => format!("D-Bus Error: lost connection: {e}"),
+            Err(e) => format!("D-Bus Error: {e}"),
+        }),
+        _ = shutdown => Ok(()),
+    };
+    resource.abort();
+    let _ = resource.await;
+    // The handler owns the sender; once no message can be dispatched to it,
+    // dropping it closes the channel
+    drop(conn.stop_receive(receiver));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Command, Stdio},
+        time::Duration,
+    };
+
+    use dbus::{
+        channel::Channel,
+        nonblock::{Proxy, stdintf::org_freedesktop_dbus::Properties},
+    };
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    const MAC: &str = "AA:BB:CC:DD:EE:FF";
+
+    fn open(address: &str) -> (IOResource<SyncConnection>, Arc<SyncConnection>) {
+        let mut channel = Channel::open_private(address).unwrap();
+        channel.register().unwrap();
+        dbus_tokio::connection::from_channel(channel).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_lights_on_a_private_bus() {
+        let Ok(mut daemon) = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+        else {
+            eprintln!("dbus-daemon not found, skipping");
+            return;
+        };
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        // The service starts from the state file and writes changes back
+        let dir = std::env::temp_dir().join(format!("batlights-dbus-{}", std::process::id()));
+        let path = dir.join("state.toml");
+        let mut state = State::default();
+        let saved = DeviceState {
+            color: Color { r: 1, g: 2, b: 3 },
+            ..DeviceState::default()
+        };
+        state.set_device(MAC, saved);
+        state.save_to(&path).unwrap();
+        let stored = || State::load_from(&path).unwrap().device(MAC);
+
+        let (resource, conn) = open(address.trim());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let lights = Lights::load(MAC, Some(path.clone()), tx).unwrap();
+        let (stop, stopped) = oneshot::channel::<()>();
+        let server = tokio::spawn(serve_on(resource, conn, lights, async {
+            let _ = stopped.await;
+        }));
+
+        let (resource, client) = open(address.trim());
+        tokio::spawn(resource);
+        let proxy = Proxy::new(BUS_NAME, OBJECT_PATH, Duration::from_secs(5), client);
+        // The service may not own its name yet
+        let mut color = Err(dbus::Error::new_failed("not called"));
+        for _ in 0..50 {
+            color = proxy.get::<(u8, u8, u8)>(INTERFACE, "Color").await;
+            if color.is_ok() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        assert_eq!(color.unwrap(), (1, 2, 3));
+
+        let () = proxy
+            .method_call(INTERFACE, "SetPower", (false,))
+            .await
+            .unwrap();
+        assert_eq!(rx.recv().await, Some(Controller::power(false)));
+        assert!(!proxy.get::<bool>(INTERFACE, "Power").await.unwrap());
+        assert!(!stored().power);
+
+        let () = proxy
+            .method_call(INTERFACE, "SetColor", (10u8, 20u8, 30u8))
+            .await
+            .unwrap();
+        let color = Color {
+            r: 10,
+            g: 20,
+            b: 30,
+        };
+        assert_eq!(rx.recv().await, Some(Controller::color(color)));
+        let color: (u8, u8, u8) = proxy.get(INTERFACE, "Color").await.unwrap();
+        assert_eq!(color, (10, 20, 30));
+        assert_eq!(
+            stored().showing(),
+            Some(Color {
+                r: 10,
+                g: 20,
+                b: 30
+            })
+        );
+
+        // Properties can be set too, and patterns are clamped
+        proxy.set(INTERFACE, "Pattern", 250u8).await.unwrap();
+        assert_eq!(rx.recv().await, Some(Controller::pattern(210)));
+        assert_eq!(proxy.get::<u8>(INTERFACE, "Pattern").await.unwrap(), 210);
+        assert_eq!((stored().mode, stored().pattern), (Mode::Pattern, 210));
+
+        // Stopping the service closes the channel to the Bluetooth task
+        stop.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        let closed = tokio::time::timeout(Duration::from_secs(5), rx.recv()).await;
+        assert_eq!(closed, Ok(None));
+
+        let _ = daemon.kill();
+        let _ = daemon.wait();
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,373 @@
+use std::{net::Ipv4Addr, ops::RangeInclusive, time::Duration};
+
+use tokio::{
+    net::UdpSocket,
+    time::{self, MissedTickBehavior},
+};
+
+use crate::{
+    bluetooth::BluetoothConnection,
+    controller::{Color, Controller},
+};
+
+const ARTNET_PORT: u16 = 6454;
+const SACN_PORT: u16 = 5568;
+
+const ARTNET_ID: &[u8] = b"Art-Net\0";
+const ARTNET_OP_DMX: u16 = 0x5000;
+const SACN_ID: &[u8] = b"ASC-E1.17\0\0\0";
+
+/// Universes as numbered by sACN. Art-Net numbers its port addresses from 0,
+/// so universe 1 is Art-Net port address 0.
+pub const UNIVERSES: RangeInclusive<i64> = 1..=63999;
+
+/// A device listening on the universe, starting at a 1-based DMX channel.
+///
+/// Each device occupies six channels: power (on at >= 128), red, green, blue,
+/// brightness, and pattern (0 shows the static color, N selects pattern N-1).
+pub struct Patch {
+    pub start: u16,
+    pub bluetooth: BluetoothConnection,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+struct DeviceState {
+    power: bool,
+    color: Color,
+    pattern: Option<u8>,
+}
+
+impl DeviceState {
+    fn from_channels(data: &[u8], start: u16) -> DeviceState {
+        let offset = start.saturating_sub(1) as usize;
+        let channel = |i: usize| data.get(offset + i).copied().unwrap_or(0);
+        DeviceState {
+            power: channel(0) >= 128,
+            color: Color {
+                r: channel(1),
+                g: channel(2),
+                b: channel(3),
+            }
+            .scaled(channel(4)),
+            pattern: channel(5).checked_sub(1),
+        }
+    }
+
+    /// Frames needed to move the strip from `prev` to this state.
+    fn frames_since(&self, prev: Option<DeviceState>) -> Vec<[u8; 9]> {
+        let mut frames = vec![];
+        let powered_on = prev.is_none_or(|p| !p.power) && self.power;
+        if prev.is_none_or(|p| p.power != self.power) {
+            frames.push(Controller::power(self.power));
+        }
+        if !self.power {
+            return frames;
+        }
+        match self.pattern {
+            Some(index) => {
+                if powered_on || prev.is_none_or(|p| p.pattern != self.pattern) {
+                    frames.push(Controller::pattern(index));
+                }
+            }
+            None => {
+                if powered_on || prev.is_none_or(|p| p.pattern.is_some() || p.color != self.color) {
+                    frames.push(Controller::color(self.color));
+                }
+            }
+        }
+        frames
+    }
+}
+
+/// Returns the DMX slots of an ArtDmx packet addressed to `universe`
+/// (the 15-bit Art-Net port address).
+pub fn parse_artnet(packet: &[u8], universe: u16) -> Option<&[u8]> {
+    if packet.len() < 18 || &packet[..8] != ARTNET_ID {
+        return None;
+    }
+    if u16::from_le_bytes([packet[8], packet[9]]) != ARTNET_OP_DMX {
+        return None;
+    }
+    if u16::from_le_bytes([packet[14], packet[15]]) & 0x7FFF != universe {
+        return None;
+    }
+    let length = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+    packet.get(18..18 + length)
+}
+
+/// Returns the DMX slots of an E1.31 data packet addressed to `universe`.
+/// Preview and stream-terminated packets are ignored, as are non-zero start codes.
+pub fn parse_sacn(packet: &[u8], universe: u16) -> Option<&[u8]> {
+    if packet.len() < 126 || &packet[4..16] != SACN_ID {
+        return None;
+    }
+    let root_vector = u32::from_be_bytes([packet[18], packet[19], packet[20], packet[21]]);
+    let framing_vector = u32::from_be_bytes([packet[40], packet[41], packet[42], packet[43]]);
+    if root_vector != 0x04 || framing_vector != 0x02 || packet[117] != 0x02 {
+        return None;
+    }
+    if packet[112] & 0xC0 != 0 {
+        return None;
+    }
+    if u16::from_be_bytes([packet[113], packet[114]]) != universe {
+        return None;
+    }
+    let count = u16::from_be_bytes([packet[123], packet[124]]) as usize;
+    if count == 0 || packet[125] != 0x00 {
+        return None;
+    }
+    packet.get(126..125 + count)
+}
+
+/// Art-Net and sACN sockets receiving one universe.
+struct Listener {
+    artnet: UdpSocket,
+    sacn: UdpSocket,
+    /// sACN universe, numbered from 1.
+    universe: u16,
+    artnet_buf: [u8; 1024],
+    sacn_buf: [u8; 1024],
+}
+
+impl Listener {
+    async fn bind(ip: Ipv4Addr, (artnet, sacn): (u16, u16), universe: u16) -> Result<Self, String> {
+        let artnet = UdpSocket::bind((ip, artnet))
+            .await
+            .map_err(|e| format!("DMX Error: Art-Net port {artnet}: {e}"))?;
+        let sacn = UdpSocket::bind((ip, sacn))
+            .await
+            .map_err(|e| format!("DMX Error: sACN port {sacn}: {e}"))?;
+        Ok(Listener {
+            artnet,
+            sacn,
+            universe,
+            artnet_buf: [0; 1024],
+            sacn_buf: [0; 1024],
+        })
+    }
+
+    /// Waits for the next packet addressed to the universe and returns its
+    /// DMX slots. Cancel-safe, so it can race the frame ticker.
+    async fn recv(&mut self) -> Result<Vec<u8>, String> {
+        let port_address = self.universe.saturating_sub(1);
+        loop {
+            let data = tokio::select! {
+                received = self.artnet.recv(&mut self.artnet_buf) => {
+                    let n = received.map_err(|e| format!("DMX Error: {e}"))?;
+                    parse_artnet(&self.artnet_buf[..n], port_address)
+                }
+                received = self.sacn.recv(&mut self.sacn_buf) => {
+                    let n = received.map_err(|e| format!("DMX Error: {e}"))?;
+                    parse_sacn(&self.sacn_buf[..n], self.universe)
+                }
+            };
+            if let Some(data) = data {
+                return Ok(data.to_vec());
+            }
+        }
+    }
+}
+
+/// Listens for Art-Net and sACN on `universe` until Ctrl-C, writing at most
+/// `fps` frames per second per device. Packets arriving between ticks are
+/// coalesced so only the latest universe state reaches the strips.
+///
+/// `universe` is numbered from 1 as in sACN; Art-Net packets are matched
+/// against port address `universe - 1`.
+pub async fn run(universe: u16, fps: u32, patches: Vec<Patch>) -> Result<(), String> {
+    let mut listener =
+        Listener::bind(Ipv4Addr::UNSPECIFIED, (ARTNET_PORT, SACN_PORT), universe).await?;
+    let group = Ipv4Addr::new(239, 255, (universe >> 8) as u8, universe as u8);
+    if let Err(e) = listener
+        .sacn
+        .join_multicast_v4(group, Ipv4Addr::UNSPECIFIED)
+    {
+        eprintln!("DMX Warning: could not join sACN multicast group {group}: {e}");
+    }
+
+    let mut ticker = time::interval(Duration::from_secs_f64(1.0 / fps.max(1) as f64));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    let mut latest: Option<Vec<u8>> = None;
+    let mut sent: Vec<Option<DeviceState>> = vec![None; patches.len()];
+
+    loop {
+        tokio::select! {
+            data = listener.recv() => latest = Some(data?),
+            _ = ticker.tick() => {
+                let Some(data) = latest.take() else {
+                    continue;
+                };
+                for (patch, last) in patches.iter().zip(sent.iter_mut()) {
+                    let state = DeviceState::from_channels(&data, patch.start);
+                    for frame in state.frames_since(*last) {
+                        if let Err(e) = patch.bluetooth.write(frame).await {
+                            eprintln!("BT Write Error: {}", e);
+                        }
+                    }
+                    *last = Some(state);
+                }
+            }
+            _ = &mut ctrl_c => break,
+        }
+    }
+
+    for patch in patches {
+        patch.bluetooth.bye().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn artnet(universe: u16, data: &[u8]) -> Vec<u8> {
+        let mut packet = ARTNET_ID.to_vec();
+        packet.extend(ARTNET_OP_DMX.to_le_bytes());
+        packet.extend([0, 14, 0, 0]);
+        packet.extend(universe.to_le_bytes());
+        packet.extend((data.len() as u16).to_be_bytes());
+        packet.extend(data);
+        packet
+    }
+
+    fn sacn(universe: u16, data: &[u8]) -> Vec<u8> {
+        let mut packet = vec![0u8; 126];
+        packet[1] = 0x10;
+        packet[4..16].copy_from_slice(SACN_ID);
+        packet[18..22].copy_from_slice(&4u32.to_be_bytes());
+        packet[40..44].copy_from_slice(&2u32.to_be_bytes());
+        packet[108] = 100;
+        packet[113..115].copy_from_slice(&universe.to_be_bytes());
+        packet[117] = 0x02;
+        packet[118] = 0xA1;
+        packet[121..123].copy_from_slice(&1u16.to_be_bytes());
+        packet[123..125].copy_from_slice(&(data.len() as u16 + 1).to_be_bytes());
+        packet.extend(data);
+        packet
+    }
+
+    const DATA: [u8; 6] = [255, 10, 20, 30, 255, 0];
+
+    #[test]
+    fn parses_artnet() {
+        assert_eq!(parse_artnet(&artnet(3, &DATA), 3), Some(&DATA[..]));
+        // The top bit of the port address is not part of the universe
+        assert_eq!(parse_artnet(&artnet(0x8003, &DATA), 3), Some(&DATA[..]));
+        assert_eq!(parse_artnet(&artnet(4, &DATA), 3), None);
+        let packet = artnet(3, &DATA);
+        assert_eq!(parse_artnet(&packet[..packet.len() - 1], 3), None);
+        assert_eq!(parse_artnet(&packet[..10], 3), None);
+        let mut poll = packet.clone();
+        poll[8..10].copy_from_slice(&0x2000u16.to_le_bytes());
+        assert_eq!(parse_artnet(&poll, 3), None);
+    }
+
+    #[test]
+    fn parses_sacn() {
+        assert_eq!(parse_sacn(&sacn(1, &DATA), 1), Some(&DATA[..]));
+        assert_eq!(parse_sacn(&sacn(2, &DATA), 1), None);
+        let packet = sacn(1, &DATA);
+        assert_eq!(parse_sacn(&packet[..packet.len() - 1], 1), None);
+        assert_eq!(parse_sacn(&packet[..100], 1), None);
+
+        let mut preview = packet.clone();
+        preview[112] = 0x80;
+        assert_eq!(parse_sacn(&preview, 1), None);
+        let mut terminated = packet.clone();
+        terminated[112] = 0x40;
+        assert_eq!(parse_sacn(&terminated, 1), None);
+        let mut start_code = packet;
+        start_code[125] = 0xDD;
+        assert_eq!(parse_sacn(&start_code, 1), None);
+    }
+
+    #[test]
+    fn reads_channels_from_the_start_address() {
+        let mut data = vec![0u8; 10];
+        data[4..10].copy_from_slice(&[200, 255, 0, 0, 128, 4]);
+        let state = DeviceState::from_channels(&data, 5);
+        assert!(state.power);
+        assert_eq!(state.color, Color { r: 128, g: 0, b: 0 });
+        assert_eq!(state.pattern, Some(3));
+        // Channels past the end of a short universe read as zero
+        assert!(!DeviceState::from_channels(&data, 11).power);
+    }
+
+    #[test]
+    fn sends_only_what_changed() {
+        let red = Color { r: 255, g: 0, b: 0 };
+        let blue = Color { r: 0, g: 0, b: 255 };
+        let on = |color, pattern| DeviceState {
+            power: true,
+            color,
+            pattern,
+        };
+        let off = DeviceState {
+            power: false,
+            color: red,
+            pattern: None,
+        };
+
+        assert_eq!(
+            on(red, None).frames_since(None),
+            vec![Controller::power(true), Controller::color(red)]
+        );
+        assert!(on(red, None).frames_since(Some(on(red, None))).is_empty());
+        assert_eq!(
+            on(blue, None).frames_since(Some(on(red, None))),
+            vec![Controller::color(blue)]
+        );
+        assert_eq!(
+            on(red, Some(2)).frames_since(Some(on(red, None))),
+            vec![Controller::pattern(2)]
+        );
+        assert_eq!(
+            on(red, None).frames_since(Some(on(red, Some(2)))),
+            vec![Controller::color(red)]
+        );
+        assert_eq!(
+            off.frames_since(Some(on(red, None))),
+            vec![Controller::power(false)]
+        );
+        assert!(off.frames_since(Some(off)).is_empty());
+        // Powering on resends the content, which the strip may have lost
+        assert_eq!(
+            on(red, None).frames_since(Some(off)),
+            vec![Controller::power(true), Controller::color(red)]
+        );
+    }
+
+    #[tokio::test]
+    async fn receives_packets_over_udp() {
+        let mut listener = Listener::bind(Ipv4Addr::LOCALHOST, (0, 0), 7)
+            .await
+            .unwrap();
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let artnet_address = listener.artnet.local_addr().unwrap();
+        let sacn_address = listener.sacn.local_addr().unwrap();
+        let other = [0u8; 6];
+
+        // Universe 7 is Art-Net port address 6; other universes are skipped
+        sender
+            .send_to(&artnet(7, &other), artnet_address)
+            .await
+            .unwrap();
+        sender
+            .send_to(&artnet(6, &DATA), artnet_address)
+            .await
+            .unwrap();
+        assert_eq!(listener.recv().await, Ok(DATA.to_vec()));
+
+        sender
+            .send_to(&sacn(6, &other), sacn_address)
+            .await
+            .unwrap();
+        sender.send_to(&sacn(7, &DATA), sacn_address).await.unwrap();
+        assert_eq!(listener.recv().await, Ok(DATA.to_vec()));
+    }
+}
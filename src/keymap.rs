@@ -0,0 +1,649 @@
+use std::{collections::BTreeMap, fmt};
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::{Deserialize, Serialize};
+
+/// Something a key can be bound to in the TUI. Actions other than the global
+/// ones mean whatever the active tab makes of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Quit,
+    Power,
+    NextTab,
+    PrevTab,
+    Undo,
+    Redo,
+    EnterValue,
+    LogUp,
+    LogDown,
+    Up,
+    Down,
+    Left,
+    Right,
+    UpCoarse,
+    DownCoarse,
+    SelectRed,
+    SelectGreen,
+    SelectBlue,
+    SelectTemperature,
+    ColorMode,
+    Favorite,
+    PrevPalette,
+    NextPalette,
+    HueDown,
+    HueUp,
+    Apply,
+    ToggleLoop,
+    Disconnect,
+    /// Applies the favorite or recent swatch with this index.
+    ApplyFavorite(usize),
+    ApplyRecent(usize),
+}
+
+/// Favorite and recent swatches that have keys of their own.
+pub const SWATCHES: usize = 9;
+
+const FAVORITE_NAMES: [&str; SWATCHES] = [
+    "apply_favorite_1",
+    "apply_favorite_2",
+    "apply_favorite_3",
+    "apply_favorite_4",
+    "apply_favorite_5",
+    "apply_favorite_6",
+    "apply_favorite_7",
+    "apply_favorite_8",
+    "apply_favorite_9",
+];
+const FAVORITE_KEYS: [&str; SWATCHES] = [
+    "alt+1", "alt+2", "alt+3", "alt+4", "alt+5", "alt+6", "alt+7", "alt+8", "alt+9",
+];
+const RECENT_NAMES: [&str; SWATCHES] = [
+    "apply_recent_1",
+    "apply_recent_2",
+    "apply_recent_3",
+    "apply_recent_4",
+    "apply_recent_5",
+    "apply_recent_6",
+    "apply_recent_7",
+    "apply_recent_8",
+    "apply_recent_9",
+];
+const RECENT_KEYS: [&str; SWATCHES] = ["f1", "f2", "f3", "f4", "f5", "f6", "f7", "f8", "f9"];
+
+impl Action {
+    const FIXED: [Action; 28] = [
+        Action::Quit,
+        Action::Power,
+        Action::NextTab,
+        Action::PrevTab,
+        Action::Undo,
+        Action::Redo,
+        Action::EnterValue,
+        Action::LogUp,
+        Action::LogDown,
+        Action::Up,
+        Action::Down,
+        Action::Left,
+        Action::Right,
+        Action::UpCoarse,
+        Action::DownCoarse,
+        Action::SelectRed,
+        Action::SelectGreen,
+        Action::SelectBlue,
+        Action::SelectTemperature,
+        Action::ColorMode,
+        Action::Favorite,
+        Action::PrevPalette,
+        Action::NextPalette,
+        Action::HueDown,
+        Action::HueUp,
+        Action::Apply,
+        Action::ToggleLoop,
+        Action::Disconnect,
+    ];
+
+    fn all() -> impl Iterator<Item = Action> {
+        Action::FIXED
+            .into_iter()
+            .chain((0..SWATCHES).map(Action::ApplyFavorite))
+            .chain((0..SWATCHES).map(Action::ApplyRecent))
+    }
+
+    /// The favorite swatch actions, in order.
+    pub fn favorites() -> [Action; SWATCHES] {
+        std::array::from_fn(Action::ApplyFavorite)
+    }
+
+    /// The recent swatch actions, in order.
+    pub fn recents() -> [Action; SWATCHES] {
+        std::array::from_fn(Action::ApplyRecent)
+    }
+
+    /// Name used in the `[keys]` section of the config.
+    fn name(self) -> &'static str {
+        match self {
+            Action::Quit => "quit",
+            Action::Power => "power",
+            Action::NextTab => "next_tab",
+            Action::PrevTab => "prev_tab",
+            Action::Undo => "undo",
+            Action::Redo => "redo",
+            Action::EnterValue => "enter_value",
+            Action::LogUp => "log_up",
+            Action::LogDown => "log_down",
+            Action::Up => "up",
+            Action::Down => "down",
+            Action::Left => "left",
+            Action::Right => "right",
+            Action::UpCoarse => "up_coarse",
+            Action::DownCoarse => "down_coarse",
+            Action::SelectRed => "select_red",
+            Action::SelectGreen => "select_green",
+            Action::SelectBlue => "select_blue",
+            Action::SelectTemperature => "select_temperature",
+            Action::ColorMode => "color_mode",
+            Action::Favorite => "favorite",
+            Action::PrevPalette => "prev_palette",
+            Action::NextPalette => "next_palette",
+            Action::HueDown => "hue_down",
+            Action::HueUp => "hue_up",
+            Action::Apply => "apply",
+            Action::ToggleLoop => "toggle_loop",
+            Action::Disconnect => "disconnect",
+            Action::ApplyFavorite(i) => FAVORITE_NAMES[i],
+            Action::ApplyRecent(i) => RECENT_NAMES[i],
+        }
+    }
+
+    fn default_keys(self) -> &'static [&'static str] {
+        match self {
+            Action::Quit => &["q"],
+            Action::Power => &["p"],
+            Action::NextTab => &["tab"],
+            Action::PrevTab => &["shift+tab"],
+            Action::Undo => &["u"],
+            Action::Redo => &["ctrl+r"],
+            Action::EnterValue => &[":"],
+            Action::LogUp => &["pageup"],
+            Action::LogDown => &["pagedown"],
+            Action::Up => &["up", "k"],
+            Action::Down => &["down", "j"],
+            Action::Left => &["left", "h"],
+            Action::Right => &["right", "l"],
+            Action::UpCoarse => &["shift+up", "K"],
+            Action::DownCoarse => &["shift+down", "J"],
+            Action::SelectRed => &["1"],
+            Action::SelectGreen => &["2"],
+            Action::SelectBlue => &["3"],
+            Action::SelectTemperature => &["4"],
+            Action::ColorMode => &["m"],
+            Action::Favorite => &["f"],
+            Action::PrevPalette => &["["],
+            Action::NextPalette => &["]"],
+            Action::HueDown => &[","],
+            Action::HueUp => &["."],
+            Action::Apply => &["enter", "space"],
+            Action::ToggleLoop => &["o"],
+            Action::Disconnect => &["d"],
+            Action::ApplyFavorite(i) => std::slice::from_ref(&FAVORITE_KEYS[i]),
+            Action::ApplyRecent(i) => std::slice::from_ref(&RECENT_KEYS[i]),
+        }
+    }
+}
+
+/// Keys bound to one action in the config: a single key or a list.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum Keys {
+    One(String),
+    Many(Vec<String>),
+}
+
+/// How far one slider moves on the fine and the coarse keys.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct Step<T> {
+    pub fine: T,
+    pub coarse: T,
+}
+
+impl<T> Step<T> {
+    pub fn get(self, coarse: bool) -> T {
+        if coarse { self.coarse } else { self.fine }
+    }
+}
+
+/// `[steps]` section of the config: how far the up and down keys and their
+/// coarse variants move each slider, e.g. `pattern = { fine = 1, coarse = 10 }`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Steps {
+    /// Red, green and blue levels.
+    pub channel: Step<u8>,
+    /// Color temperature in Kelvin.
+    pub temperature: Step<u32>,
+    pub pattern: Step<u8>,
+    pub mic: Step<u8>,
+    /// Saturation and value of the HSV picker, as fractions.
+    pub hsv: Step<f64>,
+    /// Hue of the HSV picker in degrees per key press.
+    pub hue: f64,
+}
+
+impl Default for Steps {
+    fn default() -> Self {
+        Steps {
+            channel: Step {
+                fine: 5,
+                coarse: 25,
+            },
+            temperature: Step {
+                fine: 100,
+                coarse: 500,
+            },
+            pattern: Step { fine: 1, coarse: 5 },
+            mic: Step { fine: 1, coarse: 5 },
+            hsv: Step {
+                fine: 0.05,
+                coarse: 0.25,
+            },
+            hue: 5.0,
+        }
+    }
+}
+
+impl Steps {
+    /// Rejects steps of zero, which would leave their keys doing nothing.
+    pub fn validate(&self) -> Result<(), String> {
+        let steps = [
+            ("channel.fine", self.channel.fine as f64),
+            ("channel.coarse", self.channel.coarse as f64),
+            ("temperature.fine", self.temperature.fine as f64),
+            ("temperature.coarse", self.temperature.coarse as f64),
+            ("pattern.fine", self.pattern.fine as f64),
+            ("pattern.coarse", self.pattern.coarse as f64),
+            ("mic.fine", self.mic.fine as f64),
+            ("mic.coarse", self.mic.coarse as f64),
+            ("hsv.fine", self.hsv.fine),
+            ("hsv.coarse", self.hsv.coarse),
+            ("hue", self.hue),
+        ];
+        match steps
+            .iter()
+            .find(|(_, step)| !(step.is_finite() && *step > 0.0))
+        {
+            Some((name, step)) => Err(format!(
+                "Config Error: steps.{name} must be greater than 0, got {step}"
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Key {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl Key {
+    /// Puts keys in one form: Shift is part of a character's case, and
+    /// Shift+Tab is reported as BackTab.
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Key {
+        let mut modifiers =
+            modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT | KeyModifiers::SHIFT);
+        let code = match code {
+            KeyCode::Char(c) if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::Char(c.to_ascii_uppercase())
+            }
+            KeyCode::Tab | KeyCode::BackTab if modifiers.contains(KeyModifiers::SHIFT) => {
+                modifiers.remove(KeyModifiers::SHIFT);
+                KeyCode::BackTab
+            }
+            code => code,
+        };
+        Key { code, modifiers }
+    }
+
+    /// Parses keys such as `q`, `K`, `ctrl+r`, `shift+up`, `pagedown` or `f5`.
+    fn parse(spec: &str) -> Result<Key, String> {
+        let invalid = || format!("Keymap Error: unknown key '{spec}'");
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = spec.trim();
+        // Modifiers need a key after them, so `+` alone is the plus key
+        while let Some((modifier, key)) = rest.split_once('+')
+            && !key.is_empty()
+        {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(invalid()),
+            };
+            rest = key;
+        }
+
+        let mut chars = rest.chars();
+        if let (Some(c), None) = (chars.next(), chars.next()) {
+            return Ok(Key::new(KeyCode::Char(c), modifiers));
+        }
+        let name = rest.to_lowercase();
+        let code = match name.as_str() {
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "space" => KeyCode::Char(' '),
+            "backspace" => KeyCode::Backspace,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" | "ins" => KeyCode::Insert,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" | "pgup" => KeyCode::PageUp,
+            "pagedown" | "pgdn" => KeyCode::PageDown,
+            _ => match name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                Some(n @ 1..=12) => KeyCode::F(n),
+                _ => return Err(invalid()),
+            },
+        };
+        Ok(Key::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::SHIFT) {
+            write!(f, "Shift+")?;
+        }
+        match self.code {
+            KeyCode::Up => write!(f, "↑"),
+            KeyCode::Down => write!(f, "↓"),
+            KeyCode::Left => write!(f, "←"),
+            KeyCode::Right => write!(f, "→"),
+            KeyCode::Enter => write!(f, "Enter"),
+            KeyCode::Esc => write!(f, "Esc"),
+            KeyCode::Tab => write!(f, "Tab"),
+            KeyCode::BackTab => write!(f, "Shift+Tab"),
+            KeyCode::Char(' ') => write!(f, "Space"),
+            KeyCode::Char(c) => write!(f, "{c}"),
+            KeyCode::Backspace => write!(f, "Backspace"),
+            KeyCode::Delete => write!(f, "Del"),
+            KeyCode::Insert => write!(f, "Ins"),
+            KeyCode::Home => write!(f, "Home"),
+            KeyCode::End => write!(f, "End"),
+            KeyCode::PageUp => write!(f, "PgUp"),
+            KeyCode::PageDown => write!(f, "PgDn"),
+            KeyCode::F(n) => write!(f, "F{n}"),
+            _ => write!(f, "?"),
+        }
+    }
+}
+
+/// Key bindings: the defaults, with any action named in the config bound to
+/// the keys given there instead. Keys from the config come first, so they win
+/// over a default binding of the same key, e.g. `apply_favorite_1 = "1"`
+/// takes the plain digit from `select_red`.
+pub struct Keymap {
+    bindings: Vec<(Key, Action)>,
+}
+
+impl Keymap {
+    pub fn new(overrides: &BTreeMap<String, Keys>) -> Result<Keymap, String> {
+        for name in overrides.keys() {
+            if !Action::all().any(|a| a.name() == name) {
+                let names: Vec<&str> = Action::all().map(|a| a.name()).collect();
+                return Err(format!(
+                    "Keymap Error: unknown action '{name}', expected one of {}",
+                    names.join(", ")
+                ));
+            }
+        }
+        let mut bindings = vec![];
+        for action in Action::all() {
+            let specs: Vec<&str> = match overrides.get(action.name()) {
+                Some(Keys::One(key)) => vec![key.as_str()],
+                Some(Keys::Many(keys)) => keys.iter().map(String::as_str).collect(),
+                None => action.default_keys().to_vec(),
+            };
+            for spec in specs {
+                bindings.push((Key::parse(spec)?, action));
+            }
+        }
+        // Stable, so each action keeps its keys in the order given
+        bindings.sort_by_key(|(_, action)| !overrides.contains_key(action.name()));
+        Ok(Keymap { bindings })
+    }
+
+    /// Actions bound to the pressed key, in declaration order.
+    pub fn actions(&self, event: &KeyEvent) -> Vec<Action> {
+        let key = Key::new(event.code, event.modifiers);
+        self.bindings
+            .iter()
+            .filter(|(k, _)| *k == key)
+            .map(|(_, action)| *action)
+            .collect()
+    }
+
+    /// The first key bound to `action`, for help text.
+    pub fn key(&self, action: Action) -> Option<String> {
+        self.bindings
+            .iter()
+            .find(|(_, a)| *a == action)
+            .map(|(key, _)| key.to_string())
+    }
+
+    /// The keys bound to a group of actions, such as `↑/↓`, or `None` if
+    /// nothing is bound. Longer groups show their first and last key, as in
+    /// `Alt+1…Alt+9`.
+    pub fn keys(&self, actions: &[Action]) -> Option<String> {
+        let keys: Vec<String> = actions.iter().filter_map(|a| self.key(*a)).collect();
+        match &keys[..] {
+            [] => None,
+            [first, .., last] if keys.len() > 4 => Some(format!("{first}…{last}")),
+            keys => Some(keys.join("/")),
+        }
+    }
+
+    /// Help entries such as `↑/↓: Adjust`, one per group of actions, leaving
+    /// out groups with nothing bound.
+    pub fn help(&self, entries: &[(&[Action], &str)]) -> Vec<String> {
+        entries
+            .iter()
+            .filter_map(|(actions, description)| {
+                let keys = self.keys(actions)?;
+                Some(format!("{keys}: {description}"))
+            })
+            .collect()
+    }
+
+    /// `label` followed by the keys for `actions` in parentheses, such as
+    /// `Red (1)`, or just `label` if nothing is bound.
+    pub fn title(&self, label: &str, actions: &[Action]) -> String {
+        match self.keys(actions) {
+            Some(keys) => format!("{label} ({keys})"),
+            None => label.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(code: KeyCode, modifiers: KeyModifiers) -> KeyEvent {
+        KeyEvent::new(code, modifiers)
+    }
+
+    #[test]
+    fn parses_and_displays_keys() {
+        for (spec, shown) in [
+            ("q", "q"),
+            ("K", "K"),
+            ("ctrl+r", "Ctrl+r"),
+            ("Alt+1", "Alt+1"),
+            ("shift+up", "Shift+↑"),
+            ("pagedown", "PgDn"),
+            ("pgup", "PgUp"),
+            ("enter", "Enter"),
+            ("space", "Space"),
+            ("f5", "F5"),
+            ("+", "+"),
+            ("ctrl++", "Ctrl++"),
+        ] {
+            let key = Key::parse(spec).unwrap();
+            assert_eq!(key.to_string(), shown, "{spec}");
+            // What is shown can be put in the config again, except arrows
+            if !shown.contains(['↑', '↓', '←', '→']) {
+                assert_eq!(Key::parse(shown), Ok(key), "{shown}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_keys() {
+        assert_eq!(
+            Key::parse("hyper+x"),
+            Err("Keymap Error: unknown key 'hyper+x'".to_string())
+        );
+        assert!(Key::parse("f13").is_err());
+        assert!(Key::parse("nope").is_err());
+        assert!(Key::parse("").is_err());
+    }
+
+    #[test]
+    fn normalizes_shift() {
+        // Terminals report Shift+k as K, with or without the modifier
+        assert_eq!(Key::parse("shift+k"), Key::parse("K"));
+        assert_eq!(
+            Key::new(KeyCode::Char('K'), KeyModifiers::SHIFT),
+            Key::parse("K").unwrap()
+        );
+        // and Shift+Tab as BackTab
+        let back_tab = Key::parse("backtab").unwrap();
+        assert_eq!(Key::parse("shift+tab"), Ok(back_tab));
+        assert_eq!(Key::new(KeyCode::BackTab, KeyModifiers::SHIFT), back_tab);
+        assert_eq!(back_tab.to_string(), "Shift+Tab");
+    }
+
+    #[test]
+    fn binds_defaults_and_overrides() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert("quit".to_string(), Keys::One("x".to_string()));
+        overrides.insert(
+            "apply_favorite_1".to_string(),
+            Keys::Many(vec!["ctrl+1".to_string(), "F12".to_string()]),
+        );
+        let keymap = Keymap::new(&overrides).unwrap();
+
+        let none = KeyModifiers::NONE;
+        assert_eq!(
+            keymap.actions(&event(KeyCode::Char('x'), none)),
+            vec![Action::Quit]
+        );
+        assert!(keymap.actions(&event(KeyCode::Char('q'), none)).is_empty());
+        assert_eq!(
+            keymap.actions(&event(KeyCode::F(12), none)),
+            vec![Action::ApplyFavorite(0)]
+        );
+        assert_eq!(
+            keymap.actions(&event(KeyCode::Char('2'), KeyModifiers::ALT)),
+            vec![Action::ApplyFavorite(1)]
+        );
+        assert_eq!(
+            keymap.actions(&event(KeyCode::Char('J'), KeyModifiers::SHIFT)),
+            vec![Action::DownCoarse]
+        );
+        assert_eq!(keymap.key(Action::Quit), Some("x".to_string()));
+    }
+
+    #[test]
+    fn configured_keys_win_over_defaults() {
+        // Favorites can move to the plain digits the channels use by default
+        let overrides = (1..=9)
+            .map(|i| (format!("apply_favorite_{i}"), Keys::One(i.to_string())))
+            .collect();
+        let keymap = Keymap::new(&overrides).unwrap();
+        let one = event(KeyCode::Char('1'), KeyModifiers::NONE);
+        assert_eq!(
+            keymap.actions(&one),
+            vec![Action::ApplyFavorite(0), Action::SelectRed]
+        );
+        assert_eq!(keymap.keys(&Action::favorites()), Some("1…9".to_string()));
+        assert!(
+            keymap
+                .actions(&event(KeyCode::Char('1'), KeyModifiers::ALT))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_actions() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert("jump".to_string(), Keys::One("j".to_string()));
+        let error = Keymap::new(&overrides).err().unwrap();
+        assert!(error.starts_with("Keymap Error: unknown action 'jump', expected one of quit, "));
+        assert!(error.contains("apply_recent_9"));
+
+        let mut overrides = BTreeMap::new();
+        overrides.insert("quit".to_string(), Keys::One("hyper+q".to_string()));
+        assert!(Keymap::new(&overrides).is_err());
+    }
+
+    #[test]
+    fn help_follows_the_bindings() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert("undo".to_string(), Keys::Many(vec![]));
+        let keymap = Keymap::new(&overrides).unwrap();
+        let help = keymap.help(&[
+            (&[Action::Up, Action::Down], "Adjust"),
+            (&[Action::Undo], "Undo"),
+            (&Action::favorites(), "Favorite"),
+        ]);
+        assert_eq!(help, vec!["↑/↓: Adjust", "Alt+1…Alt+9: Favorite"]);
+    }
+
+    #[test]
+    fn titles_follow_the_bindings() {
+        let mut overrides = BTreeMap::new();
+        overrides.insert("select_red".to_string(), Keys::One("r".to_string()));
+        overrides.insert("select_green".to_string(), Keys::Many(vec![]));
+        let keymap = Keymap::new(&overrides).unwrap();
+        assert_eq!(keymap.title("Red", &[Action::SelectRed]), "Red (r)");
+        assert_eq!(keymap.title("Green", &[Action::SelectGreen]), "Green");
+        assert_eq!(
+            keymap.title("Palette", &[Action::PrevPalette, Action::NextPalette]),
+            "Palette ([/])"
+        );
+    }
+
+    #[test]
+    fn validates_steps() {
+        assert_eq!(Steps::default().validate(), Ok(()));
+        let steps = Steps {
+            channel: Step {
+                fine: 0,
+                coarse: 25,
+            },
+            ..Steps::default()
+        };
+        assert_eq!(
+            steps.validate(),
+            Err("Config Error: steps.channel.fine must be greater than 0, got 0".to_string())
+        );
+        let steps = Steps {
+            hue: f64::NAN,
+            ..Steps::default()
+        };
+        assert!(steps.validate().is_err());
+    }
+}
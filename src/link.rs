@@ -0,0 +1,323 @@
+use std::time::Duration;
+
+use btleplug::{
+    api::{Central, Peripheral as _, ScanFilter},
+    platform::Adapter,
+};
+use tokio::{sync::mpsc, task::JoinHandle};
+
+use crate::{
+    bluetooth::{self, BluetoothConnection, Nearby},
+    config::DeviceConfig,
+};
+
+/// How often nearby devices, RSSI and the link's health are refreshed.
+const SCAN_INTERVAL: Duration = Duration::from_secs(2);
+/// How long to wait for a device to show up in the scan before connecting.
+const FIND_TIMEOUT: Duration = Duration::from_secs(5);
+/// Frames written per second unless `max_fps` is set in the config.
+pub const DEFAULT_MAX_FPS: u32 = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LinkStatus {
+    Connecting,
+    Connected,
+    Reconnecting,
+    Disconnected,
+}
+
+/// Requests from the TUI to the bluetooth task.
+#[derive(Debug)]
+pub enum Request {
+    Write([u8; 9]),
+    Connect(DeviceConfig),
+    Disconnect,
+}
+
+/// Reports from the bluetooth task back to the TUI.
+#[derive(Debug)]
+pub enum Feedback {
+    Status(LinkStatus),
+    /// The device being connected to.
+    Device(DeviceConfig),
+    Rssi(i16),
+    Devices(Vec<Nearby>),
+    Written {
+        payload: [u8; 9],
+        latency: Duration,
+    },
+    Error(String),
+}
+
+struct Link {
+    adapter: Adapter,
+    connection: Option<BluetoothConnection>,
+    /// Connection being set up in the background, so requests keep being
+    /// served meanwhile.
+    connecting: Option<JoinHandle<Result<BluetoothConnection, String>>>,
+    /// Frames waiting to be written, at most one per opcode.
+    pending: Vec<[u8; 9]>,
+    feedback: mpsc::UnboundedSender<Feedback>,
+}
+
+/// Queues a frame behind the others, dropping any pending frame of the same
+/// command so only the latest value is written, and in the order given.
+fn queue(pending: &mut Vec<[u8; 9]>, payload: [u8; 9]) {
+    pending.retain(|p| p[2] != payload[2]);
+    pending.push(payload);
+}
+
+impl Link {
+    fn report(&self, feedback: Feedback) {
+        let _ = self.feedback.send(feedback);
+    }
+
+    async fn connect(&mut self, device: DeviceConfig) {
+        self.disconnect().await;
+        self.report(Feedback::Device(device.clone()));
+        self.report(Feedback::Status(LinkStatus::Connecting));
+        self.connecting = Some(tokio::spawn(find_and_connect(self.adapter.clone(), device)));
+    }
+
+    /// Takes over the connection set up in the background.
+    fn connected(&mut self, result: Result<BluetoothConnection, String>) {
+        match result {
+            Ok(connection) => {
+                self.connection = Some(connection);
+                self.report(Feedback::Status(LinkStatus::Connected));
+            }
+            Err(e) => {
+                self.report(Feedback::Error(e));
+                self.report(Feedback::Status(LinkStatus::Disconnected));
+            }
+        }
+    }
+
+    async fn disconnect(&mut self) {
+        if let Some(connecting) = self.connecting.take() {
+            // Let a superseded attempt finish and hang up, rather than
+            // aborting it halfway through connecting
+            tokio::spawn(async move {
+                if let Ok(Ok(connection)) = connecting.await {
+                    let _ = connection.bye().await;
+                }
+            });
+            self.report(Feedback::Status(LinkStatus::Disconnected));
+        }
+        if let Some(connection) = self.connection.take() {
+            if let Err(e) = connection.bye().await {
+                self.report(Feedback::Error(e));
+            }
+            self.report(Feedback::Status(LinkStatus::Disconnected));
+        }
+    }
+
+    /// Reconnects a dropped link, returning whether it is usable again.
+    async fn recover(&mut self) -> bool {
+        let Some(connection) = &self.connection else {
+            return false;
+        };
+        self.report(Feedback::Status(LinkStatus::Reconnecting));
+        match connection.reconnect().await {
+            Ok(()) => {
+                self.report(Feedback::Status(LinkStatus::Connected));
+                true
+            }
+            Err(e) => {
+                self.report(Feedback::Error(e));
+                self.report(Feedback::Status(LinkStatus::Disconnected));
+                false
+            }
+        }
+    }
+
+    async fn write(&mut self, payload: [u8; 9]) {
+        let Some(connection) = &self.connection else {
+            self.report(Feedback::Error("BT Error: not connected".to_string()));
+            return;
+        };
+        let mut result = connection.try_write(payload).await;
+        if let Err(e) = result {
+            self.report(Feedback::Error(e));
+            if !self.recover().await {
+                return;
+            }
+            // Retry the frame that failed
+            result = match &self.connection {
+                Some(connection) => connection.try_write(payload).await,
+                None => return,
+            };
+        }
+        match result {
+            Ok(latency) => self.report(Feedback::Written { payload, latency }),
+            Err(e) => self.report(Feedback::Error(e)),
+        }
+    }
+
+    /// Reports a finished scan and checks the link. The link's signal comes
+    /// from the same scan, so writes never wait on it.
+    async fn scanned(&mut self, scan: Result<Vec<Nearby>, String>) {
+        let nearby = match scan {
+            Ok(nearby) => nearby,
+            Err(e) => {
+                self.report(Feedback::Error(e));
+                vec![]
+            }
+        };
+        let rssi = self.connection.as_ref().and_then(|connection| {
+            let mac = connection.peripheral.address().to_string();
+            nearby.iter().find(|n| n.mac == mac).and_then(|n| n.rssi)
+        });
+        self.report(Feedback::Devices(nearby));
+        let Some(connection) = &self.connection else {
+            return;
+        };
+        if !connection.is_connected().await {
+            self.report(Feedback::Error("BT Error: connection lost".to_string()));
+            if !self.recover().await {
+                return;
+            }
+        }
+        if let Some(rssi) = rssi {
+            self.report(Feedback::Rssi(rssi));
+        }
+    }
+}
+
+/// Lists nearby devices every `SCAN_INTERVAL` until `scans` is closed.
+/// Reading every peripheral's properties takes a while, so this runs apart
+/// from the link and its queued frames.
+async fn scan(adapter: Adapter, scans: mpsc::Sender<Result<Vec<Nearby>, String>>) {
+    let mut ticker = tokio::time::interval(SCAN_INTERVAL);
+    loop {
+        ticker.tick().await;
+        if scans.send(bluetooth::nearby(&adapter).await).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn seen(adapter: &Adapter, mac: &str) -> bool {
+    let peripherals = adapter.peripherals().await.unwrap_or_default();
+    peripherals.iter().any(|p| p.address().to_string() == mac)
+}
+
+/// Waits for `device` to show up in the scan, then connects to it.
+async fn find_and_connect(
+    adapter: Adapter,
+    device: DeviceConfig,
+) -> Result<BluetoothConnection, String> {
+    // Right after the scan starts the device may not have advertised yet
+    let deadline = tokio::time::Instant::now() + FIND_TIMEOUT;
+    while !seen(&adapter, &device.mac).await && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+    let connection = BluetoothConnection::connect(
+        &adapter,
+        device.mac.clone(),
+        crate::CHARACTERISTIC_UUID.to_string(),
+    )
+    .await?;
+    Ok(connection.calibrated(device.calibration))
+}
+
+/// Serves `requests` until the sender is dropped, scanning continuously and
+/// connecting to `initial` first if given. Writes are coalesced and sent at
+/// no more than `max_fps` frames per second.
+pub async fn run(
+    mut requests: mpsc::UnboundedReceiver<Request>,
+    feedback: mpsc::UnboundedSender<Feedback>,
+    initial: Option<DeviceConfig>,
+    max_fps: u32,
+) {
+    let adapter = match bluetooth::adapter().await {
+        Ok(adapter) => adapter,
+        Err(e) => {
+            // The TUI still works on the device's session while offline
+            if let Some(device) = initial {
+                let _ = feedback.send(Feedback::Device(device));
+            }
+            let _ = feedback.send(Feedback::Error(e));
+            let _ = feedback.send(Feedback::Status(LinkStatus::Disconnected));
+            // Keep the channel open so the TUI stays usable
+            while requests.recv().await.is_some() {}
+            return;
+        }
+    };
+    if let Err(e) = adapter.start_scan(ScanFilter::default()).await {
+        let _ = feedback.send(Feedback::Error(format!("BT Scan Error: {e}")));
+    }
+
+    // Only the latest scan matters, so the scanner waits rather than queueing
+    let (scans_tx, mut scans) = mpsc::channel(1);
+    let scanner = tokio::spawn(scan(adapter.clone(), scans_tx));
+
+    let mut link = Link {
+        adapter,
+        connection: None,
+        connecting: None,
+        pending: vec![],
+        feedback,
+    };
+    match initial {
+        Some(device) => link.connect(device).await,
+        None => link.report(Feedback::Status(LinkStatus::Disconnected)),
+    }
+
+    let mut frames = tokio::time::interval(Duration::from_secs(1) / max_fps.max(1));
+    frames.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    loop {
+        tokio::select! {
+            request = requests.recv() => match request {
+                Some(Request::Write(payload)) => queue(&mut link.pending, payload),
+                Some(Request::Connect(device)) => link.connect(device).await,
+                Some(Request::Disconnect) => {
+                    link.pending.clear();
+                    link.disconnect().await;
+                }
+                None => break,
+            },
+            result = async { link.connecting.as_mut().unwrap().await }, if link.connecting.is_some() => {
+                link.connecting = None;
+                link.connected(result.unwrap_or_else(|e| Err(format!("BT Error: {e}"))));
+            }
+            // Frames wait for a connection that is being set up
+            _ = frames.tick(), if !link.pending.is_empty() && link.connecting.is_none() => {
+                let payload = link.pending.remove(0);
+                link.write(payload).await;
+            }
+            Some(scan) = scans.recv() => link.scanned(scan).await,
+        }
+    }
+    scanner.abort();
+    link.disconnect().await;
+    let _ = link.adapter.stop_scan().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::controller::{Color, Controller};
+
+    #[test]
+    fn queue_writes_latest_frames_in_order() {
+        let red = Controller::color(Color { r: 255, g: 0, b: 0 });
+        let blue = Controller::color(Color { r: 0, g: 0, b: 255 });
+        let pattern = Controller::pattern(3);
+        let mut pending = vec![];
+        queue(&mut pending, red);
+        queue(&mut pending, pattern);
+        queue(&mut pending, blue);
+        assert_eq!(pending, vec![pattern, blue]);
+    }
+
+    #[test]
+    fn queue_keeps_power_after_content() {
+        let color = Controller::color(Color { r: 1, g: 2, b: 3 });
+        let mut pending = vec![];
+        queue(&mut pending, Controller::power(false));
+        queue(&mut pending, color);
+        queue(&mut pending, Controller::power(true));
+        assert_eq!(pending, vec![color, Controller::power(true)]);
+    }
+}
@@ -1,9 +1,32 @@
-use clap::{Parser, Subcommand, ValueEnum};
+use std::{path::PathBuf, time::Duration};
 
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+
+use crate::animation::{Animation, Easing, Effect, parse_duration};
+use crate::config::Config;
 use crate::controller::Controller;
+use crate::palette::Palette;
+use crate::state::{Mode, State};
 
+mod alarm;
+mod ambient;
+mod animation;
+mod audio;
 mod bluetooth;
+mod calibration;
+mod circadian;
+mod clock;
+mod colors;
+mod config;
 mod controller;
+mod dbus_service;
+mod dmx;
+mod keymap;
+mod link;
+mod openrgb;
+mod palette;
+mod scheduler;
+mod state;
 mod tui;
 
 #[derive(Parser, Debug)]
@@ -11,6 +34,9 @@ mod tui;
 pub struct BatLights {
     #[command(subcommand)]
     pub command: Commands,
+    /// Fade color and brightness changes over this long (e.g. 300ms, 2s)
+    #[arg(long, global = true, value_parser = parse_duration)]
+    pub transition: Option<Duration>,
 }
 
 #[derive(ValueEnum, Clone, Debug, PartialEq, PartialOrd)]
@@ -21,11 +47,270 @@ pub enum PowerState {
 
 #[derive(Subcommand, Debug, PartialEq, PartialOrd)]
 pub enum Commands {
-    Power { state: PowerState },
-    Color { r: u8, g: u8, b: u8 },
-    Pattern { index: u8 },
-    Mic { sensitivity: u8 },
+    Power {
+        state: PowerState,
+    },
+    /// Set a static color: `255 136 0`, `#ff8800`, `rgb(255,136,0)`,
+    /// `hsl(30,100%,50%)`, `hsv(30,100%,100%)`, `goldenrod` or `3000K`
+    Color {
+        #[arg(required = true, num_args = 1..=3, value_name = "COLOR")]
+        color: Vec<String>,
+    },
+    /// Set a white by color temperature, e.g. 2700K for warm room light.
+    /// The controller has no white channel, so this is mixed from RGB
+    White {
+        #[arg(value_parser = crate::colors::parse_kelvin)]
+        kelvin: u32,
+        /// Brightness level to set along with the temperature (0-255)
+        #[arg(long)]
+        brightness: Option<u8>,
+    },
+    Pattern {
+        index: u8,
+    },
+    Mic {
+        sensitivity: u8,
+    },
+    Brightness {
+        level: u8,
+    },
     Tui,
+    /// Drive the strips from an Art-Net / sACN (E1.31) DMX universe
+    Dmx {
+        /// Universe numbered from 1 as in sACN; Art-Net port addresses count
+        /// from 0, so universe 1 is Art-Net 0
+        #[arg(
+            long,
+            default_value_t = 1,
+            value_parser = clap::value_parser!(u16).range(crate::dmx::UNIVERSES)
+        )]
+        universe: u16,
+        /// Start channel for the default device, when no device in the config
+        /// sets `dmx_start`
+        #[arg(long)]
+        start_channel: Option<u16>,
+        /// Maximum frames written to each device per second
+        #[arg(long, default_value_t = 20)]
+        fps: u32,
+    },
+    /// Serve the OpenRGB SDK protocol, exposing each configured strip
+    #[command(name = "openrgb")]
+    OpenRgb {
+        #[arg(long, default_value_t = crate::openrgb::DEFAULT_PORT)]
+        port: u16,
+        /// Maximum frames written to each device per second
+        #[arg(long, default_value_t = 20)]
+        fps: u32,
+    },
+    /// Serve `org.batlights.Lights` on the D-Bus session bus
+    Dbus,
+    /// Play a client-side animation of timed color frames
+    Animate {
+        effect: Effect,
+        /// Cycle length, or total run time for candle and strobe (e.g. 500ms, 2s)
+        #[arg(long, default_value = "2s", value_parser = parse_duration)]
+        duration: Duration,
+        /// Repeat until interrupted
+        #[arg(long = "loop")]
+        looping: bool,
+        #[arg(long, value_enum, default_value_t = Easing::EaseInOut)]
+        easing: Easing,
+        /// Flicker interval for candle, flash length for strobe
+        #[arg(long, default_value = "100ms", value_parser = parse_duration)]
+        period: Duration,
+        /// Frames per second, capped at the BLE link's limit
+        #[arg(long, default_value_t = crate::animation::DEFAULT_FPS)]
+        fps: u32,
+        /// Start color of a fade, in any syntax `color` accepts
+        #[arg(long, value_name = "COLOR")]
+        from: Option<controller::Color>,
+        /// Target color of the effect, in any syntax `color` accepts
+        #[arg(long, value_name = "COLOR")]
+        to: Option<controller::Color>,
+    },
+    /// React to host audio: bass, mids and treble drive red, green and blue
+    Audio {
+        /// Read a WAV file instead of recording
+        #[arg(long, conflicts_with_all = ["stdin", "device"])]
+        wav: Option<PathBuf>,
+        /// Read raw signed 16-bit little-endian PCM from stdin
+        #[arg(long, conflicts_with = "device")]
+        stdin: bool,
+        /// PulseAudio / PipeWire source to record through `parec`
+        #[arg(long, default_value = crate::audio::PULSE_MONITOR)]
+        device: String,
+        /// Sample rate of stdin or recorded audio
+        #[arg(long, default_value_t = 44100, value_parser = clap::value_parser!(u32).range(8000..))]
+        rate: u32,
+        /// Channel count of stdin audio
+        #[arg(long, default_value_t = 1)]
+        channels: u16,
+        #[arg(long, default_value_t = crate::animation::DEFAULT_FPS)]
+        fps: u32,
+    },
+    /// Follow the color of an image, screenshot or video frames for bias lighting
+    Ambient {
+        /// Image file; re-read whenever it changes, so a screenshot path works
+        #[arg(long, required_unless_present = "stdin")]
+        image: Option<PathBuf>,
+        /// Read raw RGB24 frames of --width x --height from stdin
+        #[arg(long, conflicts_with = "image", requires_all = ["width", "height"])]
+        stdin: bool,
+        #[arg(long)]
+        width: Option<u32>,
+        #[arg(long)]
+        height: Option<u32>,
+        #[arg(long, value_enum, default_value_t = crate::ambient::Method::Edge)]
+        method: crate::ambient::Method,
+        /// Area to sample as X,Y,W,H fractions of the frame (e.g. 0,0.8,1,0.2)
+        #[arg(long, value_parser = crate::ambient::parse_region)]
+        region: Option<crate::ambient::Region>,
+        /// Border thickness for the edge method, as a fraction of the region
+        #[arg(long, default_value_t = 0.1)]
+        edge: f64,
+        /// Saturation multiplier; 1.0 leaves colors unchanged
+        #[arg(long, default_value_t = 1.2)]
+        saturation: f64,
+        /// 0 follows the source immediately, values towards 1 fade slower
+        #[arg(long, default_value_t = 0.5)]
+        smoothing: f64,
+        /// Time between updates (e.g. 100ms, 1s)
+        #[arg(long, default_value = "200ms", value_parser = parse_duration)]
+        interval: Duration,
+    },
+    /// Tune gamma and white balance for a strip, step by step
+    Calibrate {
+        /// Device name or MAC address from the config; the first by default
+        #[arg(long)]
+        device: Option<String>,
+    },
+    /// Follow the sun with color temperature and brightness, using the
+    /// `[circadian]` location and curve from the config
+    Circadian {
+        /// Latitude in degrees, overriding the config
+        #[arg(long, allow_hyphen_values = true)]
+        latitude: Option<f64>,
+        /// Longitude in degrees east, overriding the config
+        #[arg(long, allow_hyphen_values = true)]
+        longitude: Option<f64>,
+        /// Time between updates (e.g. 1m, 5m)
+        #[arg(long, default_value = "5m", value_parser = parse_duration)]
+        interval: Duration,
+        /// Apply the current point of the curve and exit
+        #[arg(long)]
+        once: bool,
+    },
+    /// Run the schedules from the config until interrupted
+    Scheduler,
+    /// List, add and remove schedules such as `weekdays 07:00 color 255 180 100`
+    Schedule {
+        #[command(subcommand)]
+        action: ScheduleAction,
+    },
+    /// Wake up to a sunrise: ramp from dim deep red to bright warm white,
+    /// reaching full brightness at TIME
+    #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+    Alarm {
+        #[command(subcommand)]
+        control: Option<AlarmControl>,
+        /// Wake-up time, HH:MM
+        #[arg(required = true, value_parser = crate::clock::parse_time)]
+        time: Option<time::Time>,
+        /// Length of the sunrise before TIME (e.g. 20m, 1h)
+        #[arg(long, default_value = "30m", value_parser = parse_duration)]
+        ramp: Duration,
+        /// Color temperature reached at TIME
+        #[arg(long, default_value = "3000K", value_parser = crate::colors::parse_kelvin)]
+        kelvin: u32,
+        /// How long to stay on after TIME, accepting snoozes
+        #[arg(long, default_value = "30m", value_parser = parse_duration)]
+        hold: Duration,
+    },
+    /// Extract, list and play color palettes
+    Palette {
+        #[command(subcommand)]
+        action: PaletteAction,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialEq, PartialOrd)]
+pub enum AlarmControl {
+    /// Turn the running alarm's lights off for a while
+    Snooze {
+        #[arg(default_value = "9m", value_parser = parse_duration)]
+        duration: Duration,
+    },
+    /// Stop the running alarm, leaving the lights as they are
+    Cancel,
+}
+
+#[derive(Subcommand, Debug, PartialEq, PartialOrd)]
+pub enum ScheduleAction {
+    /// Show the configured schedules with their next run
+    List,
+    /// Add a schedule: `[days] HH:MM action[; action...]`, where days is
+    /// daily, weekdays, weekends or a list like mon,wed-fri, and actions are
+    /// power on|off, color <color>, white <kelvin>, brightness, pattern or mic
+    Add {
+        #[arg(required = true, num_args = 1.., allow_hyphen_values = true)]
+        schedule: Vec<String>,
+    },
+    /// Remove a schedule by its number in `schedule list`
+    Remove { index: usize },
+    /// Show the upcoming runs
+    Next {
+        #[arg(long, default_value_t = 5)]
+        count: usize,
+    },
+}
+
+#[derive(Subcommand, Debug, PartialEq, PartialOrd)]
+pub enum PaletteAction {
+    /// Extract a palette from an image and save it
+    FromImage {
+        image: PathBuf,
+        /// Number of colors to extract
+        #[arg(long, default_value_t = 5)]
+        colors: usize,
+        /// Palette name; defaults to the image file name
+        #[arg(long)]
+        name: Option<String>,
+        #[arg(long, value_enum, default_value_t = crate::palette::Method::Kmeans)]
+        method: crate::palette::Method,
+    },
+    /// List saved palettes
+    List,
+    /// Import a .gpl, .ase, .css or hex list file as a saved palette
+    Import {
+        file: PathBuf,
+        /// Palette name; defaults to the file name
+        #[arg(long)]
+        name: Option<String>,
+        /// File format; detected from the extension by default
+        #[arg(long, value_enum)]
+        format: Option<crate::palette::Format>,
+    },
+    /// Write a saved palette to a .gpl, .ase, .css or hex list file
+    Export {
+        name: String,
+        file: PathBuf,
+        /// File format; detected from the extension by default
+        #[arg(long, value_enum)]
+        format: Option<crate::palette::Format>,
+    },
+    /// Cycle through a saved palette on the strip
+    Play {
+        name: String,
+        /// Fade time into each color
+        #[arg(long, default_value = "1s", value_parser = parse_duration)]
+        fade: Duration,
+        /// Time each color is held
+        #[arg(long, default_value = "2s", value_parser = parse_duration)]
+        hold: Duration,
+        /// Stop after one pass instead of looping
+        #[arg(long)]
+        once: bool,
+    },
 }
 
 const MAC_ADDR: &str = "AC:C2:01:C9:38:5D";
@@ -34,16 +319,321 @@ const CHARACTERISTIC_UUID: &str = "0000ffe1-0000-1000-8000-00805f9b34fb";
 #[tokio::main]
 async fn main() -> Result<(), String> {
     let cmd = BatLights::parse();
+    let config = Config::load()?;
+
+    // Reject malformed colors before spending seconds on a connection
+    if let Commands::Color { color } = &cmd.command
+        && let Err(e) = crate::colors::parse_args(color)
+    {
+        BatLights::command()
+            .error(clap::error::ErrorKind::ValueValidation, e)
+            .exit();
+    }
+
+    if let Commands::Dmx {
+        universe,
+        start_channel,
+        fps,
+    } = cmd.command
+    {
+        let mut patched: Vec<_> = config
+            .devices()
+            .into_iter()
+            .filter_map(|device| Some((device.dmx_start?, device)))
+            .collect();
+        if patched.is_empty() {
+            patched.push((start_channel.unwrap_or(1), config.default_device()));
+        } else if start_channel.is_some() {
+            return Err(
+                "DMX Error: --start-channel cannot be used when devices set dmx_start in the config"
+                    .to_string(),
+            );
+        }
+        let mut patches = vec![];
+        for (start, device) in patched {
+            let bluetooth = crate::bluetooth::BluetoothConnection::new(
+                device.mac,
+                CHARACTERISTIC_UUID.to_string(),
+            )
+            .await?
+            .calibrated(device.calibration);
+            patches.push(crate::dmx::Patch { start, bluetooth });
+        }
+        return crate::dmx::run(universe, fps, patches).await;
+    }
+
+    if let Commands::OpenRgb { port, fps } = cmd.command {
+        let mut devices = vec![];
+        for device in config.devices() {
+            let bluetooth = crate::bluetooth::BluetoothConnection::new(
+                device.mac.clone(),
+                CHARACTERISTIC_UUID.to_string(),
+            )
+            .await?
+            .calibrated(device.calibration);
+            let exposed = crate::openrgb::Device::new(device.name, device.mac);
+            devices.push((exposed, bluetooth));
+        }
+        return crate::openrgb::run(port, fps, devices).await;
+    }
+
+    if let Commands::Calibrate { device } = &cmd.command {
+        let device = match device {
+            Some(name) => config.device(name)?,
+            None => config.default_device(),
+        };
+        let mut bluetooth = crate::bluetooth::BluetoothConnection::new(
+            device.mac.clone(),
+            CHARACTERISTIC_UUID.to_string(),
+        )
+        .await?;
+        let result = crate::calibration::wizard(&mut bluetooth, device.calibration).await;
+        bluetooth.bye().await?;
+        let Some(calibration) = result? else {
+            println!("Calibration discarded");
+            return Ok(());
+        };
+        let mut config = config;
+        config.devices = config.devices();
+        for configured in &mut config.devices {
+            if configured.mac == device.mac {
+                configured.calibration = calibration;
+            }
+        }
+        let path = config.save()?;
+        println!(
+            "Saved calibration for '{}' to {}",
+            device.name,
+            path.display()
+        );
+        return Ok(());
+    }
+
+    if let Commands::Circadian {
+        latitude,
+        longitude,
+        interval,
+        once,
+    } = cmd.command
+    {
+        let settings = config.circadian.clone().unwrap_or_default();
+        let (Some(latitude), Some(longitude)) = (
+            latitude.or(settings.latitude),
+            longitude.or(settings.longitude),
+        ) else {
+            return Err(
+                "Circadian Error: set latitude and longitude under [circadian] in the config"
+                    .to_string(),
+            );
+        };
+        let curve = crate::circadian::Curve::parse(&settings.points)?;
+        crate::circadian::print_day(&curve, latitude, longitude);
+        let transition = cmd
+            .transition
+            .or(config.transition_ms.map(Duration::from_millis))
+            .unwrap_or_default();
+        return crate::circadian::run(
+            &curve,
+            (latitude, longitude),
+            &config.default_device(),
+            interval,
+            Duration::from_secs(settings.resume_after_minutes * 60),
+            transition,
+            once,
+        )
+        .await;
+    }
+
+    if let Commands::Scheduler = cmd.command {
+        let entries = crate::scheduler::parse_all(&config.schedules)?;
+        let transition = cmd
+            .transition
+            .or(config.transition_ms.map(Duration::from_millis))
+            .unwrap_or_default();
+        return crate::scheduler::run(
+            &entries,
+            &config.default_device(),
+            transition,
+            &crate::clock::SystemClock,
+        )
+        .await;
+    }
+
+    if let Commands::Alarm {
+        control,
+        time,
+        ramp,
+        kelvin,
+        hold,
+    } = cmd.command
+    {
+        match (control, time) {
+            (Some(AlarmControl::Snooze { duration }), _) => {
+                let target = crate::alarm::send(crate::alarm::Control::Snooze(duration))?;
+                println!("Snoozing the {target} alarm");
+            }
+            (Some(AlarmControl::Cancel), _) => {
+                let target = crate::alarm::send(crate::alarm::Control::Cancel)?;
+                println!("Cancelling the {target} alarm");
+            }
+            (None, Some(time)) => {
+                let alarm = crate::alarm::Alarm {
+                    target: crate::clock::next_at(time, crate::clock::now()),
+                    ramp,
+                    kelvin,
+                    hold,
+                };
+                crate::alarm::run(&alarm, &config.default_device()).await?;
+            }
+            (None, None) => unreachable!("clap requires a time"),
+        }
+        return Ok(());
+    }
+
+    if let Commands::Schedule { action } = &cmd.command {
+        let now = crate::clock::now();
+        match action {
+            ScheduleAction::List => {
+                let entries = crate::scheduler::parse_all(&config.schedules)?;
+                for (i, entry) in entries.iter().enumerate() {
+                    let next = crate::clock::format(entry.next_after(now));
+                    println!("{}. {entry}  (next {next})", i + 1);
+                }
+            }
+            ScheduleAction::Add { schedule } => {
+                let entry = crate::scheduler::Entry::parse(&schedule.join(" "))?;
+                let mut config = config;
+                config.schedules.push(entry.to_string());
+                let path = config.save()?;
+                println!("Added '{entry}' to {}", path.display());
+                println!("Next run {}", crate::clock::format(entry.next_after(now)));
+            }
+            ScheduleAction::Remove { index } => {
+                let mut config = config;
+                if *index == 0 || *index > config.schedules.len() {
+                    return Err(format!(
+                        "Schedule Error: no schedule {index}, see `batlights schedule list`"
+                    ));
+                }
+                let removed = config.schedules.remove(index - 1);
+                config.save()?;
+                println!("Removed '{removed}'");
+            }
+            ScheduleAction::Next { count } => {
+                let entries = crate::scheduler::parse_all(&config.schedules)?;
+                for (run, i) in crate::scheduler::upcoming(&entries, now, *count) {
+                    println!("{}  {}", crate::clock::format(run), entries[i]);
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    if let Commands::Tui = cmd.command {
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        let (feedback_tx, feedback_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        // The bluetooth task connects in the background while the TUI is up
+        let link = tokio::spawn(crate::link::run(
+            rx,
+            feedback_tx,
+            Some(config.default_device()),
+            config.max_fps.unwrap_or(crate::link::DEFAULT_MAX_FPS),
+        ));
+
+        if let Err(e) = crate::tui::run(tx, feedback_rx, &config).await {
+            eprintln!("TUI Error: {}", e);
+        }
+
+        // The bluetooth task finishes once the TUI drops its sender
+        let _ = link.await;
+        return Ok(());
+    }
+
+    match &cmd.command {
+        Commands::Palette {
+            action:
+                PaletteAction::FromImage {
+                    image,
+                    colors,
+                    name,
+                    method,
+                },
+        } => {
+            let name = match name {
+                Some(name) => name.clone(),
+                None => image
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .ok_or("Palette Error: cannot derive a name, use --name")?,
+            };
+            let palette = Palette::from_image(image, name, *colors, *method)?;
+            let path = palette.save()?;
+            for color in &palette.colors {
+                println!("{color}");
+            }
+            println!("Saved '{}' to {}", palette.name, path.display());
+            return Ok(());
+        }
+        Commands::Palette {
+            action: PaletteAction::Import { file, name, format },
+        } => {
+            let format = format
+                .or_else(|| crate::palette::Format::from_path(file))
+                .ok_or("Palette Error: unknown file type, use --format")?;
+            let name = match name {
+                Some(name) => name.clone(),
+                None => file
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .ok_or("Palette Error: cannot derive a name, use --name")?,
+            };
+            let data = std::fs::read(file)
+                .map_err(|e| format!("Palette Error: {}: {e}", file.display()))?;
+            let palette = Palette::decode(name, &data, format)?;
+            let path = palette.save()?;
+            println!(
+                "Imported {} colors as '{}' to {}",
+                palette.colors.len(),
+                palette.name,
+                path.display()
+            );
+            return Ok(());
+        }
+        Commands::Palette {
+            action: PaletteAction::Export { name, file, format },
+        } => {
+            let format = format
+                .or_else(|| crate::palette::Format::from_path(file))
+                .ok_or("Palette Error: unknown file type, use --format")?;
+            let palette = Palette::load(name)?;
+            std::fs::write(file, palette.encode(format))
+                .map_err(|e| format!("Palette Error: {}: {e}", file.display()))?;
+            return Ok(());
+        }
+        Commands::Palette {
+            action: PaletteAction::List,
+        } => {
+            for palette in Palette::load_all() {
+                let colors: Vec<String> = palette.colors.iter().map(|c| c.to_string()).collect();
+                println!("{}: {}", palette.name, colors.join(" "));
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let device = config.default_device();
     let bluetooth = crate::bluetooth::BluetoothConnection::new(
-        MAC_ADDR.to_string(),
+        device.mac.clone(),
         CHARACTERISTIC_UUID.to_string(),
     )
-    .await?;
+    .await?
+    .calibrated(device.calibration);
 
-    if let Commands::Tui = cmd.command {
-        let (tx, mut rx) = tokio::sync::mpsc::channel(100);
+    if let Commands::Dbus = cmd.command {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
 
-        // Spawn a task to handle bluetooth communication
         let bt_handle = tokio::spawn(async move {
             while let Some(payload) = rx.recv().await {
                 if let Err(e) = bluetooth.write(payload).await {
@@ -55,23 +645,174 @@ async fn main() -> Result<(), String> {
             }
         });
 
-        // Run the TUI
-        if let Err(e) = crate::tui::run(tx).await {
-            eprintln!("TUI Error: {}", e);
-        }
-
-        // Wait for the bluetooth task to finish (it finishes when tx is dropped)
+        // The service drops the sender when it returns, ending the bluetooth task
+        let served = match crate::dbus_service::Lights::load(&device.mac, State::path(), tx) {
+            Ok(lights) => crate::dbus_service::serve(lights).await,
+            Err(e) => Err(e),
+        };
         let _ = bt_handle.await;
+        served?;
+    } else if let Commands::Animate {
+        effect,
+        duration,
+        looping,
+        easing,
+        period,
+        fps,
+        from,
+        to,
+    } = cmd.command
+    {
+        let animation = Animation {
+            effect,
+            from: from.unwrap_or_default(),
+            to: to.unwrap_or(effect.default_color()),
+            duration,
+            period,
+            easing,
+            looping,
+        };
+        let played = crate::animation::run(&animation, fps, &bluetooth).await;
+        bluetooth.bye().await?;
+        played?;
+    } else if let Commands::Audio {
+        wav,
+        stdin,
+        device,
+        rate,
+        channels,
+        fps,
+    } = cmd.command
+    {
+        let source = match (wav, stdin) {
+            (Some(path), _) => crate::audio::Source::Wav(path),
+            (None, true) => crate::audio::Source::Stdin { rate, channels },
+            (None, false) => crate::audio::Source::Pulse { device, rate },
+        };
+        let played = crate::audio::run(source, fps, &bluetooth).await;
+        bluetooth.bye().await?;
+        played?;
+    } else if let Commands::Ambient {
+        image,
+        stdin: _,
+        width,
+        height,
+        method,
+        region,
+        edge,
+        saturation,
+        smoothing,
+        interval,
+    } = cmd.command
+    {
+        let source = match (image, width, height) {
+            (Some(path), _, _) => crate::ambient::Source::Image(path),
+            (None, Some(width), Some(height)) => crate::ambient::Source::Stdin { width, height },
+            _ => unreachable!("enforced by clap"),
+        };
+        let options = crate::ambient::Options {
+            method,
+            region: region.unwrap_or_default(),
+            edge,
+            saturation,
+            smoothing,
+            interval,
+        };
+        let played = crate::ambient::run(source, options, &bluetooth).await;
+        bluetooth.bye().await?;
+        played?;
+    } else if let Commands::Palette {
+        action:
+            PaletteAction::Play {
+                name,
+                fade,
+                hold,
+                once,
+            },
+    } = cmd.command
+    {
+        let palette = Palette::load(&name);
+        let played = match palette {
+            Ok(palette) => crate::palette::play(&palette, fade, hold, once, &bluetooth).await,
+            Err(e) => Err(e),
+        };
+        bluetooth.bye().await?;
+        played?;
     } else {
+        let mut state = State::load()?;
+        let mut device_state = state.device(&device.mac);
+        let from = device_state.showing();
         let payload = match cmd.command {
-            Commands::Power { state } => Controller::power(state == PowerState::On),
-            Commands::Color { r, g, b } => Controller::color(controller::Color { r, g, b }),
-            Commands::Pattern { index } => Controller::pattern(index),
-            Commands::Mic { sensitivity } => Controller::mic(sensitivity),
-            _ => unreachable!("Tui handled above"),
+            Commands::Power { state } => {
+                device_state.power = state == PowerState::On;
+                Some(Controller::power(device_state.power))
+            }
+            Commands::Color { color } => {
+                device_state.color = crate::colors::parse_args(&color)?;
+                device_state.mode = Mode::Color;
+                None
+            }
+            Commands::White { kelvin, brightness } => {
+                device_state.color = crate::colors::kelvin_to_color(kelvin);
+                if let Some(level) = brightness {
+                    device_state.brightness = level;
+                }
+                device_state.mode = Mode::Color;
+                None
+            }
+            Commands::Brightness { level } => {
+                device_state.brightness = level;
+                device_state.mode = Mode::Color;
+                None
+            }
+            Commands::Pattern { index } => {
+                device_state.mode = Mode::Pattern;
+                device_state.pattern = index;
+                Some(Controller::pattern(index))
+            }
+            Commands::Mic { sensitivity } => {
+                device_state.mode = Mode::Mic;
+                device_state.mic = sensitivity;
+                Some(Controller::mic(sensitivity))
+            }
+            Commands::Tui
+            | Commands::Dmx { .. }
+            | Commands::OpenRgb { .. }
+            | Commands::Dbus
+            | Commands::Animate { .. }
+            | Commands::Audio { .. }
+            | Commands::Ambient { .. }
+            | Commands::Calibrate { .. }
+            | Commands::Circadian { .. }
+            | Commands::Alarm { .. }
+            | Commands::Scheduler
+            | Commands::Schedule { .. }
+            | Commands::Palette { .. } => {
+                unreachable!("handled above")
+            }
         };
-        bluetooth.write(payload).await?;
+        match payload {
+            Some(payload) => bluetooth.write(payload).await?,
+            None => {
+                let duration = cmd
+                    .transition
+                    .or(config.transition_ms.map(Duration::from_millis))
+                    .unwrap_or_default();
+                let to = device_state.output();
+                // A pattern cannot be faded out of, so the color is written directly
+                let shown =
+                    crate::animation::transition(&bluetooth, from.unwrap_or(to), to, duration)
+                        .await?;
+                if shown != to {
+                    // Ctrl-C stopped the fade; record what the strip is left showing
+                    device_state.color = shown;
+                    device_state.brightness = 255;
+                }
+            }
+        }
         bluetooth.bye().await?;
+        state.set_device(&device.mac, device_state);
+        state.save()?;
     }
 
     Ok(())
@@ -0,0 +1,552 @@
+use std::{
+    io,
+    net::Ipv4Addr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::{self, MissedTickBehavior},
+};
+
+use crate::{
+    bluetooth::BluetoothConnection,
+    controller::{Color, Controller},
+};
+
+pub const DEFAULT_PORT: u16 = 6742;
+
+/// Highest SDK protocol version this server speaks.
+const PROTOCOL_VERSION: u32 = 1;
+
+const MAGIC: &[u8] = b"ORGB";
+const MAX_PACKET_SIZE: usize = 1 << 20;
+
+const REQUEST_CONTROLLER_COUNT: u32 = 0;
+const REQUEST_CONTROLLER_DATA: u32 = 1;
+const REQUEST_PROTOCOL_VERSION: u32 = 40;
+const SET_CLIENT_NAME: u32 = 50;
+const RGBCONTROLLER_RESIZEZONE: u32 = 1000;
+const RGBCONTROLLER_UPDATELEDS: u32 = 1050;
+const RGBCONTROLLER_UPDATEZONELEDS: u32 = 1051;
+const RGBCONTROLLER_UPDATESINGLELED: u32 = 1052;
+const RGBCONTROLLER_SETCUSTOMMODE: u32 = 1100;
+const RGBCONTROLLER_UPDATEMODE: u32 = 1101;
+const RGBCONTROLLER_SAVEMODE: u32 = 1102;
+
+const DEVICE_TYPE_LEDSTRIP: i32 = 4;
+const ZONE_TYPE_SINGLE: i32 = 0;
+const MODE_FLAG_HAS_PER_LED_COLOR: u32 = 1 << 5;
+const MODE_COLORS_NONE: u32 = 0;
+const MODE_COLORS_PER_LED: u32 = 1;
+
+/// Number of hardware patterns exposed as modes after "Direct".
+const PATTERN_COUNT: usize = 211;
+
+/// A strip exposed to SDK clients as a single-zone, single-LED controller.
+/// Mode 0 is "Direct" (static color); mode N selects hardware pattern N-1.
+pub struct Device {
+    name: String,
+    mac: String,
+    state: Mutex<DeviceState>,
+}
+
+#[derive(Clone, Copy)]
+struct DeviceState {
+    mode: usize,
+    color: Color,
+    /// Latest frame not yet written; clients may send faster than the strip
+    /// accepts, so older frames are replaced.
+    pending: Option<[u8; 9]>,
+}
+
+impl Device {
+    pub fn new(name: String, mac: String) -> Device {
+        Device {
+            name,
+            mac,
+            state: Mutex::new(DeviceState {
+                mode: 0,
+                color: Color {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+                pending: None,
+            }),
+        }
+    }
+
+    fn state(&self) -> DeviceState {
+        *self.state.lock().unwrap()
+    }
+
+    fn take_pending(&self) -> Option<[u8; 9]> {
+        self.state.lock().unwrap().pending.take()
+    }
+
+    fn set_color(&self, color: Color) {
+        let mut state = self.state.lock().unwrap();
+        state.mode = 0;
+        state.color = color;
+        state.pending = Some(Controller::color(color));
+    }
+
+    fn set_mode(&self, mode: usize) {
+        if mode > PATTERN_COUNT {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.mode = mode;
+        state.pending = Some(match mode {
+            0 => Controller::color(state.color),
+            n => Controller::pattern((n - 1) as u8),
+        });
+    }
+}
+
+fn put_string(buf: &mut Vec<u8>, s: &str) {
+    buf.extend_from_slice(&(s.len() as u16 + 1).to_le_bytes());
+    buf.extend_from_slice(s.as_bytes());
+    buf.push(0);
+}
+
+fn put_mode(buf: &mut Vec<u8>, name: &str, value: i32, flags: u32, color_mode: u32) {
+    put_string(buf, name);
+    buf.extend_from_slice(&value.to_le_bytes());
+    buf.extend_from_slice(&flags.to_le_bytes());
+    // speed_min, speed_max, colors_min, colors_max, speed, direction
+    buf.extend_from_slice(&[0u8; 24]);
+    buf.extend_from_slice(&color_mode.to_le_bytes());
+    buf.extend_from_slice(&0u16.to_le_bytes());
+}
+
+fn rgb_color(c: Color) -> [u8; 4] {
+    [c.r, c.g, c.b, 0]
+}
+
+fn read_color(data: &[u8], offset: usize) -> Option<Color> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(Color {
+        r: bytes[0],
+        g: bytes[1],
+        b: bytes[2],
+    })
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Serializes the controller description for `protocol`.
+fn controller_data(device: &Device, protocol: u32) -> Vec<u8> {
+    let state = device.state();
+    let mut buf = vec![0u8; 4];
+    buf.extend_from_slice(&DEVICE_TYPE_LEDSTRIP.to_le_bytes());
+    put_string(&mut buf, &device.name);
+    if protocol >= 1 {
+        put_string(&mut buf, "LEDDMX");
+    }
+    put_string(&mut buf, "Batlights BLE strip");
+    put_string(&mut buf, env!("CARGO_PKG_VERSION"));
+    put_string(&mut buf, &device.mac);
+    put_string(&mut buf, &format!("BLE: {}", device.mac));
+
+    buf.extend_from_slice(&(PATTERN_COUNT as u16 + 1).to_le_bytes());
+    buf.extend_from_slice(&(state.mode as i32).to_le_bytes());
+    put_mode(
+        &mut buf,
+        "Direct",
+        0,
+        MODE_FLAG_HAS_PER_LED_COLOR,
+        MODE_COLORS_PER_LED,
+    );
+    for index in 0..PATTERN_COUNT {
+        put_mode(
+            &mut buf,
+            &format!("Pattern {index}"),
+            index as i32 + 1,
+            0,
+            MODE_COLORS_NONE,
+        );
+    }
+
+    buf.extend_from_slice(&1u16.to_le_bytes());
+    put_string(&mut buf, "Strip");
+    buf.extend_from_slice(&ZONE_TYPE_SINGLE.to_le_bytes());
+    // leds_min, leds_max, leds_count
+    for _ in 0..3 {
+        buf.extend_from_slice(&1u32.to_le_bytes());
+    }
+    buf.extend_from_slice(&0u16.to_le_bytes());
+
+    buf.extend_from_slice(&1u16.to_le_bytes());
+    put_string(&mut buf, "Strip");
+    buf.extend_from_slice(&0u32.to_le_bytes());
+
+    buf.extend_from_slice(&1u16.to_le_bytes());
+    buf.extend_from_slice(&rgb_color(state.color));
+
+    let size = buf.len() as u32;
+    buf[..4].copy_from_slice(&size.to_le_bytes());
+    buf
+}
+
+async fn reply(stream: &mut TcpStream, device: u32, packet: u32, data: &[u8]) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(16 + data.len());
+    buf.extend_from_slice(MAGIC);
+    buf.extend_from_slice(&device.to_le_bytes());
+    buf.extend_from_slice(&packet.to_le_bytes());
+    buf.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    buf.extend_from_slice(data);
+    stream.write_all(&buf).await
+}
+
+async fn serve(mut stream: TcpStream, devices: Arc<Vec<Device>>) -> io::Result<()> {
+    loop {
+        let mut header = [0u8; 16];
+        match stream.read_exact(&mut header).await {
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        }
+        if &header[..4] != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "bad packet magic",
+            ));
+        }
+        let device_index = read_u32(&header, 4).unwrap_or_default();
+        let packet = read_u32(&header, 8).unwrap_or_default();
+        let size = read_u32(&header, 12).unwrap_or_default() as usize;
+        if size > MAX_PACKET_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "packet too large",
+            ));
+        }
+        let mut data = vec![0u8; size];
+        stream.read_exact(&mut data).await?;
+
+        let device = devices.get(device_index as usize);
+        match packet {
+            REQUEST_CONTROLLER_COUNT => {
+                reply(
+                    &mut stream,
+                    0,
+                    packet,
+                    &(devices.len() as u32).to_le_bytes(),
+                )
+                .await?;
+            }
+            REQUEST_CONTROLLER_DATA => {
+                if let Some(device) = device {
+                    let protocol = read_u32(&data, 0).unwrap_or(0).min(PROTOCOL_VERSION);
+                    let data = controller_data(device, protocol);
+                    reply(&mut stream, device_index, packet, &data).await?;
+                }
+            }
+            REQUEST_PROTOCOL_VERSION => {
+                reply(&mut stream, 0, packet, &PROTOCOL_VERSION.to_le_bytes()).await?;
+            }
+            RGBCONTROLLER_UPDATELEDS => {
+                // data_size, num_colors, colors
+                if let (Some(device), Some(color)) = (device, read_color(&data, 6)) {
+                    device.set_color(color);
+                }
+            }
+            RGBCONTROLLER_UPDATEZONELEDS => {
+                // data_size, zone_idx, num_colors, colors
+                if let (Some(device), Some(color)) = (device, read_color(&data, 10)) {
+                    device.set_color(color);
+                }
+            }
+            RGBCONTROLLER_UPDATESINGLELED => {
+                // led_idx, color
+                if let (Some(device), Some(color)) = (device, read_color(&data, 4)) {
+                    device.set_color(color);
+                }
+            }
+            RGBCONTROLLER_SETCUSTOMMODE => {
+                if let Some(device) = device {
+                    device.set_mode(0);
+                }
+            }
+            RGBCONTROLLER_UPDATEMODE | RGBCONTROLLER_SAVEMODE => {
+                // data_size, mode_idx, mode
+                if let (Some(device), Some(mode)) = (device, read_u32(&data, 4)) {
+                    device.set_mode(mode as usize);
+                }
+            }
+            // Single fixed zone, nothing to resize; client names are not tracked.
+            SET_CLIENT_NAME | RGBCONTROLLER_RESIZEZONE => {}
+            _ => {}
+        }
+    }
+}
+
+/// Serves the OpenRGB SDK protocol on `port` until Ctrl-C, writing at most
+/// `fps` frames per second per device. Updates arriving between ticks are
+/// coalesced so only the latest one reaches each strip.
+pub async fn run(
+    port: u16,
+    fps: u32,
+    devices: Vec<(Device, BluetoothConnection)>,
+) -> Result<(), String> {
+    let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))
+        .await
+        .map_err(|e| format!("OpenRGB Error: port {port}: {e}"))?;
+    let (devices, connections): (Vec<_>, Vec<_>) = devices.into_iter().unzip();
+    let devices = Arc::new(devices);
+
+    let mut ticker = time::interval(Duration::from_secs_f64(1.0 / fps.max(1) as f64));
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Skip);
+
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = accepted.map_err(|e| format!("OpenRGB Error: {e}"))?;
+                let devices = devices.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, devices).await {
+                        eprintln!("OpenRGB Client Error ({peer}): {e}");
+                    }
+                });
+            }
+            _ = ticker.tick() => {
+                for (device, bluetooth) in devices.iter().zip(&connections) {
+                    let Some(payload) = device.take_pending() else {
+                        continue;
+                    };
+                    if let Err(e) = bluetooth.write(payload).await {
+                        eprintln!("BT Write Error: {}", e);
+                    }
+                }
+            }
+            _ = &mut ctrl_c => break,
+        }
+    }
+
+    for bluetooth in connections {
+        bluetooth.bye().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads the little-endian fields of a controller description in order.
+    struct Reader<'a> {
+        data: &'a [u8],
+        offset: usize,
+    }
+
+    impl Reader<'_> {
+        fn bytes(&mut self, n: usize) -> &[u8] {
+            let bytes = &self.data[self.offset..self.offset + n];
+            self.offset += n;
+            bytes
+        }
+
+        fn u16(&mut self) -> u16 {
+            let b = self.bytes(2);
+            u16::from_le_bytes([b[0], b[1]])
+        }
+
+        fn u32(&mut self) -> u32 {
+            let b = self.bytes(4);
+            u32::from_le_bytes([b[0], b[1], b[2], b[3]])
+        }
+
+        fn string(&mut self) -> String {
+            let length = self.u16() as usize;
+            let bytes = self.bytes(length);
+            assert_eq!(bytes.last(), Some(&0), "strings are null-terminated");
+            String::from_utf8(bytes[..length - 1].to_vec()).unwrap()
+        }
+
+        fn mode(&mut self) -> (String, u32, u32, u32) {
+            let name = self.string();
+            let value = self.u32();
+            let flags = self.u32();
+            self.bytes(24);
+            let color_mode = self.u32();
+            assert_eq!(self.u16(), 0, "modes carry no colors");
+            (name, value, flags, color_mode)
+        }
+    }
+
+    fn device() -> Device {
+        Device::new("Desk".to_string(), "AC:C2:01:C9:38:5D".to_string())
+    }
+
+    #[test]
+    fn describes_a_single_led_strip() {
+        let device = device();
+        device.set_mode(3);
+        let data = controller_data(&device, 1);
+        let mut reader = Reader {
+            data: &data,
+            offset: 0,
+        };
+        assert_eq!(reader.u32() as usize, data.len());
+        assert_eq!(reader.u32() as i32, DEVICE_TYPE_LEDSTRIP);
+        assert_eq!(reader.string(), "Desk");
+        assert_eq!(reader.string(), "LEDDMX");
+        assert_eq!(reader.string(), "Batlights BLE strip");
+        assert_eq!(reader.string(), env!("CARGO_PKG_VERSION"));
+        assert_eq!(reader.string(), "AC:C2:01:C9:38:5D");
+        assert_eq!(reader.string(), "BLE: AC:C2:01:C9:38:5D");
+
+        assert_eq!(reader.u16() as usize, PATTERN_COUNT + 1);
+        assert_eq!(reader.u32(), 3, "active mode");
+        assert_eq!(
+            reader.mode(),
+            (
+                "Direct".to_string(),
+                0,
+                MODE_FLAG_HAS_PER_LED_COLOR,
+                MODE_COLORS_PER_LED
+            )
+        );
+        for index in 0..PATTERN_COUNT as u32 {
+            let (name, value, flags, color_mode) = reader.mode();
+            assert_eq!(name, format!("Pattern {index}"));
+            assert_eq!((value, flags, color_mode), (index + 1, 0, MODE_COLORS_NONE));
+        }
+
+        assert_eq!(reader.u16(), 1, "zone count");
+        assert_eq!(reader.string(), "Strip");
+        assert_eq!(reader.u32() as i32, ZONE_TYPE_SINGLE);
+        assert_eq!([reader.u32(), reader.u32(), reader.u32()], [1, 1, 1]);
+        assert_eq!(reader.u16(), 0, "no matrix map");
+
+        assert_eq!(reader.u16(), 1, "LED count");
+        assert_eq!(reader.string(), "Strip");
+        assert_eq!(reader.u32(), 0);
+
+        assert_eq!(reader.u16(), 1, "color count");
+        assert_eq!(reader.bytes(4), [255, 255, 255, 0]);
+        assert_eq!(reader.offset, data.len());
+    }
+
+    #[test]
+    fn protocol_zero_has_no_vendor() {
+        let data = controller_data(&device(), 0);
+        let mut reader = Reader {
+            data: &data,
+            offset: 8,
+        };
+        assert_eq!(reader.string(), "Desk");
+        assert_eq!(reader.string(), "Batlights BLE strip");
+        assert_eq!(controller_data(&device(), 1).len(), data.len() + 9);
+    }
+
+    fn packet(device: u32, id: u32, data: &[u8]) -> Vec<u8> {
+        let mut buf = MAGIC.to_vec();
+        buf.extend(device.to_le_bytes());
+        buf.extend(id.to_le_bytes());
+        buf.extend((data.len() as u32).to_le_bytes());
+        buf.extend(data);
+        buf
+    }
+
+    /// Sends `packet`, then waits for the reply to a controller count
+    /// request so the server has handled it.
+    async fn send(stream: &mut TcpStream, packet: &[u8]) -> u32 {
+        stream.write_all(packet).await.unwrap();
+        stream
+            .write_all(&self::packet(0, REQUEST_CONTROLLER_COUNT, &[]))
+            .await
+            .unwrap();
+        let mut reply = [0u8; 20];
+        stream.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply[..4], MAGIC);
+        read_u32(&reply, 16).unwrap()
+    }
+
+    #[tokio::test]
+    async fn dispatches_packets_over_tcp() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let devices = Arc::new(vec![device(), device()]);
+        let server = tokio::spawn({
+            let devices = devices.clone();
+            async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                serve(stream, devices).await
+            }
+        });
+        let mut client = TcpStream::connect(address).await.unwrap();
+        let color = |r, g, b| Color { r, g, b };
+
+        // data_size, num_colors, colors
+        let mut leds = vec![0u8; 6];
+        leds.extend([10, 20, 30, 0]);
+        assert_eq!(
+            send(&mut client, &packet(1, RGBCONTROLLER_UPDATELEDS, &leds)).await,
+            2
+        );
+        assert_eq!(devices[1].state().color, color(10, 20, 30));
+        assert_eq!(
+            devices[1].take_pending(),
+            Some(Controller::color(color(10, 20, 30)))
+        );
+        assert_eq!(devices[0].take_pending(), None);
+
+        // data_size, zone_idx, num_colors, colors
+        let mut zone = vec![0u8; 10];
+        zone.extend([40, 50, 60, 0]);
+        send(&mut client, &packet(0, RGBCONTROLLER_UPDATEZONELEDS, &zone)).await;
+        assert_eq!(devices[0].state().color, color(40, 50, 60));
+
+        // led_idx, color
+        let mut single = vec![0u8; 4];
+        single.extend([70, 80, 90, 0]);
+        send(
+            &mut client,
+            &packet(0, RGBCONTROLLER_UPDATESINGLELED, &single),
+        )
+        .await;
+        assert_eq!(devices[0].state().color, color(70, 80, 90));
+        // Only the latest frame waits to be written
+        assert_eq!(
+            devices[0].take_pending(),
+            Some(Controller::color(color(70, 80, 90)))
+        );
+
+        // data_size, mode_idx, mode
+        let mut mode = vec![0u8; 4];
+        mode.extend(6u32.to_le_bytes());
+        send(&mut client, &packet(0, RGBCONTROLLER_UPDATEMODE, &mode)).await;
+        assert_eq!(devices[0].state().mode, 6);
+        assert_eq!(devices[0].take_pending(), Some(Controller::pattern(5)));
+
+        let mut out_of_range = vec![0u8; 4];
+        out_of_range.extend((PATTERN_COUNT as u32 + 1).to_le_bytes());
+        send(
+            &mut client,
+            &packet(0, RGBCONTROLLER_UPDATEMODE, &out_of_range),
+        )
+        .await;
+        assert_eq!(devices[0].state().mode, 6);
+
+        send(&mut client, &packet(0, RGBCONTROLLER_SETCUSTOMMODE, &[])).await;
+        assert_eq!(devices[0].state().mode, 0);
+        assert_eq!(
+            devices[0].take_pending(),
+            Some(Controller::color(color(70, 80, 90)))
+        );
+
+        drop(client);
+        assert!(server.await.unwrap().is_ok());
+    }
+}
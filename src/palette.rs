@@ -0,0 +1,621 @@
+use std::{fs, path::Path, path::PathBuf, time::Duration};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    animation,
+    bluetooth::BluetoothConnection,
+    config::Config,
+    controller::{Color, Controller},
+};
+
+/// Pixels considered when extracting a palette; larger images are strided.
+const MAX_SAMPLES: usize = 20_000;
+const KMEANS_ITERATIONS: usize = 12;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Method {
+    /// Recursively split the color cube at the median of its widest channel
+    MedianCut,
+    /// Refine median-cut clusters with k-means in OKLab
+    Kmeans,
+}
+
+/// Interchange formats for `palette import` / `palette export`.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum Format {
+    /// GIMP palette (.gpl)
+    Gpl,
+    /// Adobe Swatch Exchange (.ase)
+    Ase,
+    /// CSS custom properties (.css)
+    Css,
+    /// One hex color per line (.hex, .txt)
+    Hex,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Option<Format> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "gpl" => Some(Format::Gpl),
+            "ase" => Some(Format::Ase),
+            "css" => Some(Format::Css),
+            "hex" | "txt" => Some(Format::Hex),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: Vec<Color>,
+}
+
+/// On-disk form: colors as `#rrggbb` strings.
+#[derive(Serialize, Deserialize)]
+struct PaletteFile {
+    colors: Vec<String>,
+}
+
+impl Palette {
+    pub fn dir() -> Option<PathBuf> {
+        Config::dir().map(|d| d.join("palettes"))
+    }
+
+    fn path(name: &str) -> Result<PathBuf, String> {
+        // Names become file names, which must stay inside the palettes dir
+        if name.is_empty() || name.contains(['/', '\\']) || name.contains("..") {
+            return Err(format!("Palette Error: invalid palette name '{name}'"));
+        }
+        let dir = Self::dir().ok_or("Palette Error: No config directory available")?;
+        Ok(dir.join(format!("{name}.toml")))
+    }
+
+    pub fn load(name: &str) -> Result<Palette, String> {
+        let path = Self::path(name)?;
+        let text = fs::read_to_string(&path)
+            .map_err(|e| format!("Palette Error: {}: {e}", path.display()))?;
+        let file: PaletteFile =
+            toml::from_str(&text).map_err(|e| format!("Palette Error: {}: {e}", path.display()))?;
+        let colors = file
+            .colors
+            .iter()
+            .map(|c| {
+                Color::from_hex(c)
+                    .ok_or_else(|| format!("Palette Error: {}: bad color '{c}'", path.display()))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Palette {
+            name: name.to_string(),
+            colors,
+        })
+    }
+
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let path = Self::path(&self.name)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("Palette Error: {e}"))?;
+        }
+        let file = PaletteFile {
+            colors: self.colors.iter().map(|c| c.to_string()).collect(),
+        };
+        let text = toml::to_string_pretty(&file).map_err(|e| format!("Palette Error: {e}"))?;
+        fs::write(&path, text).map_err(|e| format!("Palette Error: {}: {e}", path.display()))?;
+        Ok(path)
+    }
+
+    /// Names of all saved palettes, sorted.
+    pub fn list() -> Vec<String> {
+        let Some(dir) = Self::dir() else {
+            return vec![];
+        };
+        let Ok(entries) = fs::read_dir(dir) else {
+            return vec![];
+        };
+        let mut names: Vec<String> = entries
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.extension().is_some_and(|ext| ext == "toml"))
+            .filter_map(|p| p.file_stem().map(|s| s.to_string_lossy().into_owned()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    /// Loads every saved palette, skipping files that fail to parse.
+    pub fn load_all() -> Vec<Palette> {
+        Self::list()
+            .iter()
+            .filter_map(|name| Palette::load(name).ok())
+            .collect()
+    }
+
+    /// Extracts `count` representative colors from an image file.
+    pub fn from_image(
+        path: &Path,
+        name: String,
+        count: usize,
+        method: Method,
+    ) -> Result<Palette, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("Palette Error: {}: {e}", path.display()))?
+            .into_rgb8();
+        let step = (image.pixels().len() / MAX_SAMPLES).max(1);
+        let pixels: Vec<[u8; 3]> = image.pixels().step_by(step).map(|p| p.0).collect();
+        if pixels.is_empty() {
+            return Err(format!("Palette Error: {} has no pixels", path.display()));
+        }
+
+        let mut clusters = median_cut(pixels, count.max(1));
+        if method == Method::Kmeans {
+            clusters = kmeans(clusters);
+        }
+        clusters.sort_by_key(|c| std::cmp::Reverse(c.len()));
+        Ok(Palette {
+            name,
+            colors: clusters.iter().map(|c| mean(c)).collect(),
+        })
+    }
+
+    pub fn decode(name: String, data: &[u8], format: Format) -> Result<Palette, String> {
+        let colors = match format {
+            Format::Gpl => decode_gpl(&String::from_utf8_lossy(data))?,
+            Format::Ase => decode_ase(data)?,
+            Format::Css | Format::Hex => decode_hex_tokens(&String::from_utf8_lossy(data)),
+        };
+        if colors.is_empty() {
+            return Err("Palette Error: no colors found".to_string());
+        }
+        Ok(Palette { name, colors })
+    }
+
+    pub fn encode(&self, format: Format) -> Vec<u8> {
+        match format {
+            Format::Gpl => {
+                let mut out = format!("GIMP Palette\nName: {}\nColumns: 0\n#\n", self.name);
+                for (i, c) in self.colors.iter().enumerate() {
+                    out += &format!("{:3} {:3} {:3}\t{} {}\n", c.r, c.g, c.b, self.name, i + 1);
+                }
+                out.into_bytes()
+            }
+            Format::Ase => encode_ase(self),
+            Format::Css => {
+                let mut out = String::from(":root {\n");
+                for (i, c) in self.colors.iter().enumerate() {
+                    out += &format!("  --{}-{}: {c};\n", css_ident(&self.name), i + 1);
+                }
+                out += "}\n";
+                out.into_bytes()
+            }
+            Format::Hex => self
+                .colors
+                .iter()
+                .map(|c| format!("{c}\n"))
+                .collect::<String>()
+                .into_bytes(),
+        }
+    }
+}
+
+fn css_ident(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_lowercase()
+            } else {
+                '-'
+            }
+        })
+        .collect()
+}
+
+fn decode_gpl(text: &str) -> Result<Vec<Color>, String> {
+    let mut lines = text.lines();
+    if lines.next().map(str::trim) != Some("GIMP Palette") {
+        return Err("Palette Error: missing 'GIMP Palette' header".to_string());
+    }
+    let mut colors = vec![];
+    for line in lines {
+        let line = line.trim();
+        // Skips the Name/Columns headers, comments and blank lines
+        if !line.starts_with(|c: char| c.is_ascii_digit()) {
+            continue;
+        }
+        let values: Vec<u8> = line
+            .split_whitespace()
+            .take(3)
+            .map(|v| v.parse::<u8>())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Palette Error: bad color line '{line}'"))?;
+        if let [r, g, b] = values[..] {
+            colors.push(Color { r, g, b });
+        }
+    }
+    Ok(colors)
+}
+
+/// Collects the `#rgb` / `#rrggbb` tokens in value position: starting a
+/// line, or after `:`, `=` or a list separator. Selectors such as `#add {`
+/// are skipped.
+fn decode_hex_tokens(text: &str) -> Vec<Color> {
+    let mut colors = vec![];
+    for (start, _) in text.match_indices('#') {
+        let rest = &text[start + 1..];
+        let end = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-' || c == '_'))
+            .unwrap_or(rest.len());
+        let token = &rest[..end];
+        let hex = match token.len() {
+            3 => token.chars().flat_map(|c| [c, c]).collect(),
+            6 => token.to_string(),
+            _ => continue,
+        };
+        let before = text[..start].trim_end_matches([' ', '\t', '"', '\'']);
+        let in_value = matches!(
+            before.chars().last(),
+            None | Some('\n' | '\r' | ':' | '=' | ',' | '(' | '[')
+        );
+        // A rule's selector runs up to its `{`, a value up to `;` or `}`
+        let in_selector = rest[end..].chars().find(|c| matches!(c, '{' | ';' | '}')) == Some('{');
+        if in_value
+            && !in_selector
+            && let Some(color) = Color::from_hex(&hex)
+        {
+            colors.push(color);
+        }
+    }
+    colors
+}
+
+const ASE_COLOR_ENTRY: u16 = 0x0001;
+
+fn decode_ase(data: &[u8]) -> Result<Vec<Color>, String> {
+    let bad = || "Palette Error: truncated ASE file".to_string();
+    let u16_at = |o: usize| data.get(o..o + 2).map(|b| u16::from_be_bytes([b[0], b[1]]));
+    let u32_at = |o: usize| {
+        data.get(o..o + 4)
+            .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let f32_at = |o: usize| u32_at(o).map(f32::from_bits);
+
+    if data.get(..4) != Some(b"ASEF") {
+        return Err("Palette Error: missing 'ASEF' signature".to_string());
+    }
+    let blocks = u32_at(8).ok_or_else(bad)?;
+    let mut offset = 12;
+    let mut colors = vec![];
+    for _ in 0..blocks {
+        let kind = u16_at(offset).ok_or_else(bad)?;
+        let length = u32_at(offset + 2).ok_or_else(bad)? as usize;
+        let body = offset + 6;
+        if kind == ASE_COLOR_ENTRY {
+            let name_units = u16_at(body).ok_or_else(bad)? as usize;
+            let model_at = body + 2 + name_units * 2;
+            let model = data.get(model_at..model_at + 4).ok_or_else(bad)?;
+            let value = |i: usize| f32_at(model_at + 4 + i * 4).ok_or_else(bad);
+            let channel = |v: f32| (v * 255.0).round().clamp(0.0, 255.0) as u8;
+            let color = match model {
+                b"RGB " => Color {
+                    r: channel(value(0)?),
+                    g: channel(value(1)?),
+                    b: channel(value(2)?),
+                },
+                b"Gray" => {
+                    let v = channel(value(0)?);
+                    Color { r: v, g: v, b: v }
+                }
+                b"CMYK" => {
+                    let k = 1.0 - value(3)?;
+                    Color {
+                        r: channel((1.0 - value(0)?) * k),
+                        g: channel((1.0 - value(1)?) * k),
+                        b: channel((1.0 - value(2)?) * k),
+                    }
+                }
+                b"LAB " => lab_to_color(value(0)? * 100.0, value(1)?, value(2)?),
+                _ => {
+                    offset = body + length;
+                    continue;
+                }
+            };
+            colors.push(color);
+        }
+        offset = body + length;
+    }
+    Ok(colors)
+}
+
+/// CIELAB (D50, as used by ASE) to sRGB.
+fn lab_to_color(l: f32, a: f32, b: f32) -> Color {
+    let fy = (l + 16.0) / 116.0;
+    let fx = fy + a / 500.0;
+    let fz = fy - b / 200.0;
+    let f_inv = |t: f32| {
+        if t > 6.0 / 29.0 {
+            t.powi(3)
+        } else {
+            3.0 * (6.0f32 / 29.0).powi(2) * (t - 4.0 / 29.0)
+        }
+    };
+    let (x, y, z) = (0.9642 * f_inv(fx), f_inv(fy), 0.8251 * f_inv(fz));
+    // Bradford-adapted XYZ (D50) to linear sRGB
+    let r = 3.1339 * x - 1.6169 * y - 0.4906 * z;
+    let g = -0.9788 * x + 1.9161 * y + 0.0335 * z;
+    let bl = 0.0719 * x - 0.2290 * y + 1.4052 * z;
+    let srgb = |v: f32| {
+        let v = if v <= 0.0031308 {
+            12.92 * v
+        } else {
+            1.055 * v.powf(1.0 / 2.4) - 0.055
+        };
+        (v * 255.0).round().clamp(0.0, 255.0) as u8
+    };
+    Color {
+        r: srgb(r),
+        g: srgb(g),
+        b: srgb(bl),
+    }
+}
+
+fn encode_ase(palette: &Palette) -> Vec<u8> {
+    let mut out = b"ASEF".to_vec();
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&0u16.to_be_bytes());
+    out.extend_from_slice(&(palette.colors.len() as u32).to_be_bytes());
+    for (i, c) in palette.colors.iter().enumerate() {
+        let name: Vec<u16> = format!("{} {}", palette.name, i + 1)
+            .encode_utf16()
+            .chain([0])
+            .collect();
+        let mut body = (name.len() as u16).to_be_bytes().to_vec();
+        for unit in &name {
+            body.extend_from_slice(&unit.to_be_bytes());
+        }
+        body.extend_from_slice(b"RGB ");
+        for v in [c.r, c.g, c.b] {
+            body.extend_from_slice(&(v as f32 / 255.0).to_be_bytes());
+        }
+        // Color type: global
+        body.extend_from_slice(&0u16.to_be_bytes());
+
+        out.extend_from_slice(&ASE_COLOR_ENTRY.to_be_bytes());
+        out.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        out.extend_from_slice(&body);
+    }
+    out
+}
+
+fn mean(pixels: &[[u8; 3]]) -> Color {
+    let n = pixels.len().max(1) as u64;
+    let sum = |i: usize| pixels.iter().map(|p| p[i] as u64).sum::<u64>() / n;
+    Color {
+        r: sum(0) as u8,
+        g: sum(1) as u8,
+        b: sum(2) as u8,
+    }
+}
+
+fn median_cut(pixels: Vec<[u8; 3]>, count: usize) -> Vec<Vec<[u8; 3]>> {
+    let mut buckets = vec![pixels];
+    while buckets.len() < count {
+        // Split the bucket with the widest channel range
+        let widest = |bucket: &Vec<[u8; 3]>| {
+            (0..3)
+                .map(|i| {
+                    let (lo, hi) = bucket
+                        .iter()
+                        .fold((255u8, 0u8), |(lo, hi), p| (lo.min(p[i]), hi.max(p[i])));
+                    (hi.saturating_sub(lo), i)
+                })
+                .max()
+                .unwrap_or((0, 0))
+        };
+        let Some((index, (range, channel))) = buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.len() > 1)
+            .map(|(i, b)| (i, widest(b)))
+            .max_by_key(|(_, (range, _))| *range)
+        else {
+            break;
+        };
+        if range == 0 {
+            break;
+        }
+        let mut bucket = buckets.swap_remove(index);
+        bucket.sort_unstable_by_key(|p| p[channel]);
+        let upper = bucket.split_off(bucket.len() / 2);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+    buckets
+}
+
+fn kmeans(clusters: Vec<Vec<[u8; 3]>>) -> Vec<Vec<[u8; 3]>> {
+    let lab = |p: &[u8; 3]| {
+        Color {
+            r: p[0],
+            g: p[1],
+            b: p[2],
+        }
+        .to_oklab()
+    };
+    let mut centroids: Vec<[f64; 3]> = clusters.iter().map(|c| mean(c).to_oklab()).collect();
+    let pixels: Vec<[u8; 3]> = clusters.into_iter().flatten().collect();
+    let points: Vec<[f64; 3]> = pixels.iter().map(lab).collect();
+    let mut assignment = vec![0usize; points.len()];
+
+    for _ in 0..KMEANS_ITERATIONS {
+        let mut moved = false;
+        for (point, assigned) in points.iter().zip(assignment.iter_mut()) {
+            let nearest = centroids
+                .iter()
+                .enumerate()
+                .map(|(i, c)| (i, (0..3).map(|k| (point[k] - c[k]).powi(2)).sum::<f64>()))
+                .min_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(i, _)| i)
+                .unwrap_or(0);
+            moved |= nearest != *assigned;
+            *assigned = nearest;
+        }
+        for (i, centroid) in centroids.iter_mut().enumerate() {
+            let members: Vec<&[f64; 3]> = points
+                .iter()
+                .zip(&assignment)
+                .filter(|(_, a)| **a == i)
+                .map(|(p, _)| p)
+                .collect();
+            if members.is_empty() {
+                continue;
+            }
+            for k in 0..3 {
+                centroid[k] = members.iter().map(|p| p[k]).sum::<f64>() / members.len() as f64;
+            }
+        }
+        if !moved {
+            break;
+        }
+    }
+
+    let mut result = vec![vec![]; centroids.len()];
+    for (pixel, assigned) in pixels.into_iter().zip(assignment) {
+        result[assigned].push(pixel);
+    }
+    result.retain(|c| !c.is_empty());
+    result
+}
+
+/// Cycles through the palette, fading into each color over `fade` and
+/// holding it for `hold`, until Ctrl-C (or after one pass with `once`).
+pub async fn play(
+    palette: &Palette,
+    fade: Duration,
+    hold: Duration,
+    once: bool,
+    bluetooth: &BluetoothConnection,
+) -> Result<(), String> {
+    if palette.colors.is_empty() {
+        return Err(format!("Palette Error: '{}' is empty", palette.name));
+    }
+    let cycle = async {
+        let mut previous = None;
+        loop {
+            for color in &palette.colors {
+                match previous {
+                    Some(from) => {
+                        animation::transition(bluetooth, from, *color, fade).await?;
+                    }
+                    None => bluetooth.write(Controller::color(*color)).await?,
+                }
+                previous = Some(*color);
+                tokio::time::sleep(hold).await;
+            }
+            if once {
+                return Ok::<(), String>(());
+            }
+        }
+    };
+    tokio::select! {
+        played = cycle => played,
+        _ = tokio::signal::ctrl_c() => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_stay_inside_the_palettes_dir() {
+        for name in ["../../x", "a/b", "a\\b", "..", ""] {
+            assert_eq!(
+                Palette::path(name),
+                Err(format!("Palette Error: invalid palette name '{name}'")),
+            );
+        }
+        if let Some(dir) = Palette::dir() {
+            assert_eq!(Palette::path("sunset.v2"), Ok(dir.join("sunset.v2.toml")));
+        }
+    }
+
+    fn sample() -> Palette {
+        Palette {
+            name: "Sunset 2".to_string(),
+            colors: vec![
+                Color {
+                    r: 255,
+                    g: 136,
+                    b: 0,
+                },
+                Color { r: 0, g: 0, b: 0 },
+                Color {
+                    r: 1,
+                    g: 254,
+                    b: 127,
+                },
+                Color {
+                    r: 255,
+                    g: 255,
+                    b: 255,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        let palette = sample();
+        for format in [Format::Gpl, Format::Ase, Format::Css, Format::Hex] {
+            let data = palette.encode(format);
+            let decoded = Palette::decode(palette.name.clone(), &data, format);
+            assert_eq!(decoded, Ok(palette.clone()), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn reads_formats_from_other_tools() {
+        let gpl = "GIMP Palette\nName: x\n# comment\n\n  0 128 255\tBlue\n";
+        assert_eq!(
+            decode_gpl(gpl),
+            Ok(vec![Color {
+                r: 0,
+                g: 128,
+                b: 255
+            }])
+        );
+        assert!(decode_gpl("not a palette").is_err());
+        assert!(decode_gpl("GIMP Palette\n300 0 0\n").is_err());
+
+        let mut ase = encode_ase(&sample());
+        ase.truncate(ase.len() - 3);
+        assert_eq!(
+            decode_ase(&ase),
+            Err("Palette Error: truncated ASE file".to_string())
+        );
+        assert!(decode_ase(b"RIFF").is_err());
+        assert_eq!(
+            Palette::decode("x".to_string(), b"nothing here", Format::Hex),
+            Err("Palette Error: no colors found".to_string())
+        );
+    }
+
+    #[test]
+    fn hex_tokens_only_count_as_values() {
+        let css = "#add { color: #bad; }\n\
+                   #fade, #cab .x { background: linear-gradient(#123, #456789) }\n\
+                   :root { --accent: #0f0; --id: #abcdef0; }\n\
+                   a:hover #bee { color: red }\n";
+        let hex = |s: &str| Color::from_hex(s).unwrap();
+        assert_eq!(
+            decode_hex_tokens(css),
+            vec![hex("bbaadd"), hex("112233"), hex("456789"), hex("00ff00")]
+        );
+        assert_eq!(
+            decode_hex_tokens("#ff8800\n  #fff\n# comment\n[\"#000\", \"#123456\"]\n"),
+            vec![hex("ff8800"), hex("ffffff"), hex("000000"), hex("123456")]
+        );
+    }
+}
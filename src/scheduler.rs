@@ -0,0 +1,518 @@
+use std::{fmt, time::Duration};
+
+use time::{OffsetDateTime, Time};
+
+use crate::{
+    bluetooth::BluetoothConnection,
+    clock::{Clock, parse_time},
+    colors::{kelvin_to_color, parse_kelvin},
+    config::DeviceConfig,
+    controller::{Color, Controller},
+    state::{Mode, State},
+};
+
+/// Longest sleep between clock checks, so suspend and clock changes are
+/// noticed without waiting for the next run.
+const MAX_SLEEP: Duration = Duration::from_secs(30);
+
+/// Day names, from Monday. Their first three letters are accepted too.
+const DAYS: [&str; 7] = [
+    "monday",
+    "tuesday",
+    "wednesday",
+    "thursday",
+    "friday",
+    "saturday",
+    "sunday",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Power(bool),
+    Color(Color),
+    White(u32),
+    Brightness(u8),
+    Pattern(u8),
+    Mic(u8),
+}
+
+/// One schedule line: `[days] HH:MM action[; action...]`, e.g.
+/// `weekdays 07:00 color 255 180 100` or `23:30 power off`.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Indexed by days from Monday.
+    days: [bool; 7],
+    time: Time,
+    pub actions: Vec<Action>,
+    source: String,
+}
+
+fn parse_days(word: &str) -> Option<[bool; 7]> {
+    let day = |name: &str| DAYS.iter().position(|d| name == *d || name == &d[..3]);
+    match word {
+        "daily" | "everyday" => return Some([true; 7]),
+        "weekdays" => return Some([true, true, true, true, true, false, false]),
+        "weekends" => return Some([false, false, false, false, false, true, true]),
+        _ => {}
+    }
+    let mut days = [false; 7];
+    for part in word.split(',') {
+        match part.split_once('-') {
+            Some((from, to)) => {
+                let (from, to) = (day(from)?, day(to)?);
+                // Ranges may wrap, as in fri-mon
+                let mut d = from;
+                loop {
+                    days[d] = true;
+                    if d == to {
+                        break;
+                    }
+                    d = (d + 1) % 7;
+                }
+            }
+            None => days[day(part)?] = true,
+        }
+    }
+    Some(days)
+}
+
+fn parse_action(words: &[&str]) -> Result<Action, String> {
+    let number = |what: &str| -> Result<u8, String> {
+        match words {
+            [_, value] => value
+                .parse()
+                .map_err(|_| format!("invalid {what} '{value}', expected 0-255")),
+            _ => Err(format!("'{}' takes one {what} value", words[0])),
+        }
+    };
+    match words {
+        ["power", "on"] => Ok(Action::Power(true)),
+        ["power", "off"] => Ok(Action::Power(false)),
+        ["power", ..] => Err("expected 'power on' or 'power off'".to_string()),
+        ["color", spec @ ..] if !spec.is_empty() => spec.join(" ").parse().map(Action::Color),
+        ["white", kelvin] => parse_kelvin(kelvin).map(Action::White),
+        ["brightness", ..] => number("brightness").map(Action::Brightness),
+        ["pattern", ..] => number("pattern").map(Action::Pattern),
+        ["mic", ..] => number("sensitivity").map(Action::Mic),
+        [] => Err("missing action".to_string()),
+        [other, ..] => Err(format!(
+            "unknown action '{other}', expected power, color, white, brightness, pattern or mic"
+        )),
+    }
+}
+
+impl Entry {
+    pub fn parse(line: &str) -> Result<Entry, String> {
+        let invalid = |reason: String| format!("Schedule Error: '{}': {reason}", line.trim());
+        let lower = line.trim().to_lowercase();
+        let mut words = lower.split_whitespace();
+
+        let first = words
+            .next()
+            .ok_or_else(|| invalid("empty schedule".to_string()))?;
+        let (days, time) = match parse_time(first) {
+            Ok(time) => ([true; 7], time),
+            Err(_) => {
+                let days = parse_days(first).ok_or_else(|| {
+                    invalid(format!(
+                        "unknown days '{first}', expected e.g. daily, weekdays or mon,wed-fri"
+                    ))
+                })?;
+                let word = words.next().unwrap_or_default();
+                let time = parse_time(word).map_err(invalid)?;
+                (days, time)
+            }
+        };
+
+        let rest = words.collect::<Vec<_>>().join(" ");
+        let actions = rest
+            .split(';')
+            .map(|action| {
+                let words: Vec<&str> = action.split_whitespace().collect();
+                parse_action(&words).map_err(invalid)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Entry {
+            days,
+            time,
+            actions,
+            source: line.trim().to_string(),
+        })
+    }
+
+    /// First run strictly after `after`, in `after`'s UTC offset.
+    pub fn next_after(&self, after: OffsetDateTime) -> OffsetDateTime {
+        let mut date = after.date();
+        loop {
+            let run = date.with_time(self.time).assume_offset(after.offset());
+            if run > after && self.days[date.weekday().number_days_from_monday() as usize] {
+                return run;
+            }
+            date = date.next_day().unwrap_or(date);
+        }
+    }
+}
+
+impl fmt::Display for Entry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+/// Parses every schedule line from the config.
+pub fn parse_all(lines: &[String]) -> Result<Vec<Entry>, String> {
+    lines.iter().map(|line| Entry::parse(line)).collect()
+}
+
+/// The next `count` runs after `after`, with the index of their entry.
+pub fn upcoming(
+    entries: &[Entry],
+    after: OffsetDateTime,
+    count: usize,
+) -> Vec<(OffsetDateTime, usize)> {
+    let mut runs = vec![];
+    let mut after: Vec<OffsetDateTime> = vec![after; entries.len()];
+    while runs.len() < count && !entries.is_empty() {
+        let (i, run) = entries
+            .iter()
+            .enumerate()
+            .map(|(i, e)| (i, e.next_after(after[i])))
+            .min_by_key(|(_, run)| *run)
+            .unwrap_or((0, after[0]));
+        runs.push((run, i));
+        after[i] = run;
+    }
+    runs
+}
+
+/// Connects to the device and performs `actions`, recording the result in the
+/// state file like the equivalent one-shot commands.
+pub async fn execute(
+    actions: &[Action],
+    device: &DeviceConfig,
+    transition: Duration,
+) -> Result<(), String> {
+    let mut state = State::load()?;
+    let mut device_state = state.device(&device.mac);
+    let bluetooth =
+        BluetoothConnection::new(device.mac.clone(), crate::CHARACTERISTIC_UUID.to_string())
+            .await?
+            .calibrated(device.calibration);
+
+    let mut result = Ok(());
+    for action in actions {
+        let from = device_state.showing();
+        let payload = match *action {
+            Action::Power(on) => {
+                device_state.power = on;
+                Some(Controller::power(on))
+            }
+            Action::Color(color) => {
+                device_state.color = color;
+                device_state.mode = Mode::Color;
+                None
+            }
+            Action::White(kelvin) => {
+                device_state.color = kelvin_to_color(kelvin);
+                device_state.mode = Mode::Color;
+                None
+            }
+            Action::Brightness(level) => {
+                device_state.brightness = level;
+                device_state.mode = Mode::Color;
+                None
+            }
+            Action::Pattern(index) => {
+                device_state.mode = Mode::Pattern;
+                device_state.pattern = index;
+                Some(Controller::pattern(index))
+            }
+            Action::Mic(sensitivity) => {
+                device_state.mode = Mode::Mic;
+                device_state.mic = sensitivity;
+                Some(Controller::mic(sensitivity))
+            }
+        };
+        result = match payload {
+            Some(payload) => bluetooth.write(payload).await,
+            None => {
+                let to = device_state.output();
+                let shown =
+                    crate::animation::transition(&bluetooth, from.unwrap_or(to), to, transition)
+                        .await;
+                shown.map(|shown| {
+                    // A fade cut short by Ctrl-C leaves its last frame on the strip
+                    if shown != to {
+                        device_state.color = shown;
+                        device_state.brightness = 255;
+                    }
+                })
+            }
+        };
+        if result.is_err() {
+            break;
+        }
+    }
+    bluetooth.bye().await?;
+    result?;
+    state.set_device(&device.mac, device_state);
+    state.save()
+}
+
+/// The entries with a run after `last` and no later than `clock`'s current
+/// time, each once however many of its runs were missed, along with the time
+/// to check from next. Runs are local times, so when the UTC offset changes
+/// `last` keeps its local time, and a DST change neither skips nor repeats a
+/// run.
+pub fn due<'a>(
+    entries: &'a [Entry],
+    last: OffsetDateTime,
+    clock: &dyn Clock,
+) -> (Vec<&'a Entry>, OffsetDateTime) {
+    let now = clock.now();
+    let last = last.replace_offset(now.offset());
+    let due: Vec<&Entry> = entries
+        .iter()
+        .filter(|e| e.next_after(last) <= now)
+        .collect();
+    let next = if due.is_empty() { last } else { now };
+    (due, next)
+}
+
+/// Runs `entries` as they come due until Ctrl-C. Runs missed while the
+/// machine was asleep are performed once on wake-up.
+pub async fn run(
+    entries: &[Entry],
+    device: &DeviceConfig,
+    transition: Duration,
+    clock: &dyn Clock,
+) -> Result<(), String> {
+    if entries.is_empty() {
+        return Err("Schedule Error: no schedules configured, see `batlights schedule add`".into());
+    }
+    let ctrl_c = tokio::signal::ctrl_c();
+    tokio::pin!(ctrl_c);
+
+    let mut last = clock.now();
+    loop {
+        let (entries_due, next) = due(entries, last, clock);
+        last = next;
+        for entry in entries_due {
+            println!("{} {entry}", crate::clock::format(last));
+            if let Err(e) = execute(&entry.actions, device, transition).await {
+                // Keep running; the strip may be out of range for now
+                eprintln!("{e}");
+            }
+        }
+
+        let Some(next) = entries.iter().map(|e| e.next_after(last)).min() else {
+            return Ok(());
+        };
+        let wait = Duration::try_from(next - clock.now()).unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait.min(MAX_SLEEP)) => {}
+            _ = &mut ctrl_c => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use time::{Date, Month, UtcOffset, Weekday};
+
+    use super::*;
+
+    /// A clock stopped at one instant.
+    struct FixedClock(OffsetDateTime);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> OffsetDateTime {
+            self.0
+        }
+    }
+
+    fn at(month: Month, day: u8, hour: u8, minute: u8, offset: i8) -> OffsetDateTime {
+        Date::from_calendar_date(2026, month, day)
+            .unwrap()
+            .with_hms(hour, minute, 0)
+            .unwrap()
+            .assume_offset(UtcOffset::from_hms(offset, 0, 0).unwrap())
+    }
+
+    #[test]
+    fn parses_entries() {
+        let entry = Entry::parse("Weekdays 07:00 color 255 180 100; power on").unwrap();
+        assert_eq!(entry.days, [true, true, true, true, true, false, false]);
+        assert_eq!(entry.time, Time::from_hms(7, 0, 0).unwrap());
+        assert_eq!(
+            entry.actions,
+            vec![
+                Action::Color(Color {
+                    r: 255,
+                    g: 180,
+                    b: 100
+                }),
+                Action::Power(true)
+            ]
+        );
+        assert_eq!(
+            entry.to_string(),
+            "Weekdays 07:00 color 255 180 100; power on"
+        );
+
+        let entry = Entry::parse("23:30 power off").unwrap();
+        assert_eq!(entry.days, [true; 7]);
+        assert_eq!(entry.actions, vec![Action::Power(false)]);
+
+        let entry = Entry::parse("fri-mon,wed 06:15 pattern 4").unwrap();
+        assert_eq!(entry.days, [true, false, true, false, true, true, true]);
+        assert_eq!(entry.actions, vec![Action::Pattern(4)]);
+    }
+
+    #[test]
+    fn rejects_invalid_entries() {
+        let error = |line: &str| Entry::parse(line).unwrap_err();
+        assert!(error("").contains("empty schedule"));
+        assert!(error("someday 07:00 power on").contains("unknown days 'someday'"));
+        assert!(error("daily 7am power on").contains("invalid time '7am'"));
+        assert!(error("07:00 dance").contains("unknown action 'dance'"));
+        assert!(error("07:00 power maybe").contains("'power on' or 'power off'"));
+        assert!(error("07:00 pattern 300").contains("invalid pattern '300'"));
+        assert!(error("07:00").contains("missing action"));
+        assert!(error("monkey 07:00 power on").contains("unknown days 'monkey'"));
+        assert!(error("mo 07:00 power on").contains("unknown days 'mo'"));
+        assert!(error("tues 07:00 power on").contains("unknown days 'tues'"));
+    }
+
+    #[test]
+    fn parses_day_names() {
+        assert_eq!(parse_days("monday"), parse_days("mon"));
+        assert_eq!(
+            parse_days("monday,wed-friday"),
+            Some([true, false, true, true, true, false, false])
+        );
+        assert_eq!(
+            parse_days("sat-sun"),
+            Some([false, false, false, false, false, true, true])
+        );
+        assert_eq!(parse_days("sundays"), None);
+    }
+
+    #[test]
+    fn next_after_skips_to_matching_days() {
+        let weekdays = Entry::parse("weekdays 07:00 power on").unwrap();
+        let weekends = Entry::parse("weekends 09:00 power on").unwrap();
+        // Friday evening
+        let friday = at(Month::October, 16, 20, 0, 2);
+        assert_eq!(friday.weekday(), Weekday::Friday);
+        assert_eq!(weekdays.next_after(friday), at(Month::October, 19, 7, 0, 2));
+        assert_eq!(weekends.next_after(friday), at(Month::October, 17, 9, 0, 2));
+        // Later the same day, and strictly after a run
+        let morning = at(Month::October, 16, 6, 59, 2);
+        assert_eq!(
+            weekdays.next_after(morning),
+            at(Month::October, 16, 7, 0, 2)
+        );
+        let run = at(Month::October, 16, 7, 0, 2);
+        assert_eq!(weekdays.next_after(run), at(Month::October, 19, 7, 0, 2));
+        // Sunday rolls over to Saturday
+        let sunday = at(Month::October, 18, 10, 0, 2);
+        assert_eq!(weekends.next_after(sunday), at(Month::October, 24, 9, 0, 2));
+    }
+
+    #[test]
+    fn next_after_keeps_local_time_across_dst() {
+        let entry = Entry::parse("daily 07:00 power on").unwrap();
+        // Saturday night in CET; the clocks go forward to CEST that night
+        let before = at(Month::March, 28, 23, 0, 1);
+        let after = before.to_offset(UtcOffset::from_hms(2, 0, 0).unwrap());
+        let run = entry.next_after(after);
+        assert_eq!(run, at(Month::March, 29, 7, 0, 2));
+        assert_eq!((run.hour(), run.minute()), (7, 0));
+    }
+
+    #[test]
+    fn upcoming_lists_runs_in_order() {
+        let entries = parse_all(&[
+            "weekdays 07:00 power on".to_string(),
+            "daily 23:30 power off".to_string(),
+        ])
+        .unwrap();
+        let clock = FixedClock(at(Month::October, 16, 12, 0, 2));
+        let runs = upcoming(&entries, clock.now(), 4);
+        assert_eq!(
+            runs,
+            vec![
+                (at(Month::October, 16, 23, 30, 2), 1),
+                (at(Month::October, 17, 23, 30, 2), 1),
+                (at(Month::October, 18, 23, 30, 2), 1),
+                (at(Month::October, 19, 7, 0, 2), 0),
+            ]
+        );
+        assert!(upcoming(&[], clock.now(), 4).is_empty());
+    }
+
+    fn due_sources(entries: &[Entry], last: OffsetDateTime, now: OffsetDateTime) -> Vec<String> {
+        let (due, _) = due(entries, last, &FixedClock(now));
+        due.iter().map(|e| e.to_string()).collect()
+    }
+
+    #[test]
+    fn due_runs_missed_entries_once() {
+        let entries = parse_all(&[
+            "daily 07:00 power on".to_string(),
+            "daily 23:30 power off".to_string(),
+            "weekdays 12:00 white 4000".to_string(),
+            "sat 12:00 pattern 2".to_string(),
+        ])
+        .unwrap();
+        // Friday morning, then asleep until Monday
+        let last = at(Month::October, 16, 6, 0, 2);
+        let now = at(Month::October, 19, 8, 0, 2);
+        let (missed, next) = due(&entries, last, &FixedClock(now));
+        assert_eq!(missed.len(), 4, "each missed entry runs once");
+        assert_eq!(next, now);
+        // Nothing is due again until the next run
+        let (again, unchanged) = due(&entries, next, &FixedClock(now));
+        assert!(again.is_empty());
+        assert_eq!(unchanged, now);
+
+        // Only the runs between the last check and now
+        assert_eq!(
+            due_sources(&entries, last, at(Month::October, 16, 7, 0, 2)),
+            vec!["daily 07:00 power on"]
+        );
+        assert!(due_sources(&entries, last, at(Month::October, 16, 6, 59, 2)).is_empty());
+    }
+
+    #[test]
+    fn due_follows_dst_changes() {
+        let entries = parse_all(&[
+            "daily 07:00 power on".to_string(),
+            "daily 23:30 power off".to_string(),
+        ])
+        .unwrap();
+        // Checked Saturday night in CET; the clocks go forward to CEST
+        let last = at(Month::March, 28, 23, 45, 1);
+        // 06:59 CEST is already 05:59 CET, but the run is at 07:00 local
+        assert!(due_sources(&entries, last, at(Month::March, 29, 6, 59, 2)).is_empty());
+        assert_eq!(
+            due_sources(&entries, last, at(Month::March, 29, 7, 0, 2)),
+            vec!["daily 07:00 power on"]
+        );
+
+        // Back to CET in October: 23:30 CEST already ran and is not repeated
+        let last = at(Month::October, 24, 23, 30, 2);
+        assert!(due_sources(&entries, last, at(Month::October, 25, 6, 59, 1)).is_empty());
+        assert_eq!(
+            due_sources(&entries, last, at(Month::October, 25, 7, 0, 1)),
+            vec!["daily 07:00 power on"]
+        );
+
+        // The repeated hour does not run an entry twice
+        let entries = parse_all(&["daily 02:30 pattern 1".to_string()]).unwrap();
+        let last = at(Month::October, 25, 2, 30, 2);
+        assert!(due_sources(&entries, last, at(Month::October, 25, 2, 45, 1)).is_empty());
+    }
+}
@@ -0,0 +1,141 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller::Color;
+
+/// Last state sent to each device, keyed by MAC address. The strips cannot
+/// be queried, so this is the only record of what they are showing.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(default)]
+pub struct State {
+    pub devices: BTreeMap<String, DeviceState>,
+    /// Colors saved as favorites in the TUI.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub favorite_colors: Vec<Color>,
+    /// Colors recently sent from the TUI, newest first.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub recent_colors: Vec<Color>,
+    /// TUI session per device, keyed by MAC address.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tui: BTreeMap<String, TuiState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct DeviceState {
+    pub power: bool,
+    /// Color before brightness is applied.
+    pub color: Color,
+    pub brightness: u8,
+    /// Whether the strip shows the color, a built-in pattern or the mic mode.
+    pub mode: Mode,
+    pub pattern: u8,
+    pub mic: u8,
+}
+
+impl Default for DeviceState {
+    fn default() -> Self {
+        DeviceState {
+            power: true,
+            color: Color {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+            brightness: 255,
+            mode: Mode::Color,
+            pattern: 0,
+            mic: 0,
+        }
+    }
+}
+
+/// The kind of command the TUI sent last, which decides what the strip is
+/// showing when the session is restored.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Color,
+    Pattern,
+    Mic,
+}
+
+/// What the TUI last sent to a device.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TuiState {
+    pub power: bool,
+    pub color: Color,
+    pub pattern: u8,
+    pub mic: u8,
+    #[serde(default)]
+    pub mode: Mode,
+}
+
+impl DeviceState {
+    /// The color actually written to the strip.
+    pub fn output(&self) -> Color {
+        self.color.scaled(self.brightness)
+    }
+
+    /// The color on the strip, or `None` while a pattern or the mic mode runs.
+    pub fn showing(&self) -> Option<Color> {
+        (self.mode == Mode::Color).then(|| self.output())
+    }
+}
+
+impl State {
+    pub fn dir() -> Option<PathBuf> {
+        dirs::state_dir()
+            .or_else(dirs::data_local_dir)
+            .map(|d| d.join("batlights"))
+    }
+
+    pub fn path() -> Option<PathBuf> {
+        Self::dir().map(|d| d.join("state.toml"))
+    }
+
+    pub fn load() -> Result<State, String> {
+        match Self::path() {
+            Some(path) => Self::load_from(&path),
+            None => Ok(State::default()),
+        }
+    }
+
+    /// Reads the state file at `path`; a missing file is an empty state.
+    pub fn load_from(path: &Path) -> Result<State, String> {
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                toml::from_str(&text).map_err(|e| format!("State Error: {}: {e}", path.display()))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(State::default()),
+            Err(e) => Err(format!("State Error: {}: {e}", path.display())),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let path = Self::path().ok_or("State Error: No state directory available")?;
+        self.save_to(&path)
+    }
+
+    pub fn save_to(&self, path: &Path) -> Result<(), String> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(|e| format!("State Error: {e}"))?;
+        }
+        let text = toml::to_string_pretty(self).map_err(|e| format!("State Error: {e}"))?;
+        fs::write(path, text).map_err(|e| format!("State Error: {}: {e}", path.display()))
+    }
+
+    pub fn device(&self, mac: &str) -> DeviceState {
+        self.devices.get(mac).copied().unwrap_or_default()
+    }
+
+    pub fn set_device(&mut self, mac: &str, state: DeviceState) {
+        self.devices.insert(mac.to_string(), state);
+    }
+}
@@ -1,50 +1,295 @@
+use clap::ValueEnum;
 use crossterm::{
-    event::{self, Event, KeyCode, KeyEvent, KeyEventKind},
+    event::{
+        self, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind,
+        MouseButton, MouseEvent, MouseEventKind,
+    },
     execute,
     terminal::{EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode, enable_raw_mode},
 };
 use ratatui::{
     Frame, Terminal,
-    layout::{Constraint, Direction, Layout, Rect},
+    layout::{Constraint, Direction, Layout, Margin, Position, Rect},
     style::{Color, Modifier, Style},
+    symbols::Marker,
     text::{Line, Span},
     widgets::{
         Block, Borders, Gauge, Paragraph, Tabs, Wrap,
         canvas::{Canvas, Line as CanvasLine, Shape},
     },
 };
-use std::{error::Error, io, time::Duration};
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    error::Error,
+    io,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc;
 
+use crate::animation::{Animation, Easing, Effect};
+use crate::bluetooth::Nearby;
+use crate::colors::kelvin_to_color;
+use crate::config::{Config, DeviceConfig};
 use crate::controller::{Color as LightColor, Controller};
+use crate::keymap::{Action, Keymap, Step, Steps};
+use crate::link::{Feedback, LinkStatus, Request};
+use crate::palette::Palette;
+use crate::state::{DeviceState, Mode, State, TuiState};
 
 #[derive(Clone, Copy, PartialEq)]
 enum ActiveTab {
     Color,
     Pattern,
     Mic,
+    Animate,
+    History,
+    Devices,
+}
+
+impl ActiveTab {
+    const ALL: [ActiveTab; 6] = [
+        ActiveTab::Color,
+        ActiveTab::Pattern,
+        ActiveTab::Mic,
+        ActiveTab::Animate,
+        ActiveTab::History,
+        ActiveTab::Devices,
+    ];
+
+    fn index(self) -> usize {
+        Self::ALL.iter().position(|t| *t == self).unwrap_or(0)
+    }
+
+    fn next(self) -> Self {
+        Self::ALL[(self.index() + 1) % Self::ALL.len()]
+    }
+
+    fn prev(self) -> Self {
+        Self::ALL[(self.index() + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            ActiveTab::Color => "Color",
+            ActiveTab::Pattern => "Pattern",
+            ActiveTab::Mic => "Mic",
+            ActiveTab::Animate => "Animate",
+            ActiveTab::History => "History",
+            ActiveTab::Devices => "Devices",
+        }
+    }
+}
+
+const ANIMATION_FPS: u64 = 20;
+
+/// Log lines kept for the log panel.
+const LOG_CAPACITY: usize = 200;
+
+fn status_label(status: LinkStatus) -> (&'static str, Color) {
+    match status {
+        LinkStatus::Connecting => ("Connecting", Color::Yellow),
+        LinkStatus::Connected => ("Connected", Color::Green),
+        LinkStatus::Reconnecting => ("Reconnecting", Color::Yellow),
+        LinkStatus::Disconnected => ("Disconnected", Color::Red),
+    }
+}
+
+/// A row of the Devices tab: a configured device, a scanned one, or both.
+struct DeviceRow {
+    name: String,
+    mac: String,
+    rssi: Option<i16>,
+    configured: bool,
+}
+
+struct LogEntry {
+    at: Instant,
+    text: String,
+    error: bool,
+}
+
+/// Short description of a frame for the log.
+fn describe(payload: &[u8; 9]) -> String {
+    match payload[2] {
+        0x04 => format!("power {}", if payload[3] == 0x03 { "on" } else { "off" }),
+        0x07 => format!(
+            "color #{:02x}{:02x}{:02x}",
+            payload[3], payload[4], payload[5]
+        ),
+        0x03 => format!("pattern {}", payload[3]),
+        0x0B => format!("mic {}", payload[3]),
+        _ => payload
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ColorMode {
+    Rgb,
+    Hsv,
+}
+
+/// Favorites and recent colors kept, one per number key.
+const SWATCH_CAPACITY: usize = crate::keymap::SWATCHES;
+/// Columns taken by each favorite or recent swatch.
+const SWATCH_WIDTH: u16 = 4;
+/// Colors sent closer together than this replace each other in the recent
+/// list, so a drag or held key leaves a single entry.
+const RECENT_MERGE: Duration = Duration::from_secs(1);
+
+/// Undo steps kept.
+const HISTORY_CAPACITY: usize = 100;
+
+/// Highest pattern index the controller accepts.
+const MAX_PATTERN: u8 = 210;
+
+/// Text typed after `:`, with the error from the last attempt to apply it.
+struct Input {
+    text: String,
+    error: Option<String>,
+}
+
+/// Screen areas from the last frame, for mapping mouse clicks. Areas not on
+/// screen are left empty.
+#[derive(Clone, Copy, Default)]
+struct Areas {
+    tabs: Rect,
+    channels: [Rect; 4], // R, G, B, Temperature
+    mic: Rect,
+    pattern: Rect,
+    sv_field: Rect,
+    hue_bar: Rect,
+    favorites: Rect,
+    recent: Rect,
+}
+
+/// What a left-button drag adjusts, fixed when the button goes down.
+#[derive(Clone, Copy, PartialEq)]
+enum DragTarget {
+    Channel(usize),
+    Mic,
+    SvField,
+    HueBar,
+}
+
+/// How far `at` is across and up `area`, clamped to 0.0-1.0 so a drag can
+/// leave the area.
+fn fraction(area: Rect, at: Position) -> (f64, f64) {
+    let span = |n: u16| n.saturating_sub(1).max(1) as f64;
+    (
+        (at.x.saturating_sub(area.x) as f64 / span(area.width)).min(1.0),
+        1.0 - (at.y.saturating_sub(area.y) as f64 / span(area.height)).min(1.0),
+    )
+}
+
+/// The tab whose title is under `at`, given the tab bar's inner area.
+fn tab_at(area: Rect, at: Position) -> Option<ActiveTab> {
+    if !area.contains(at) {
+        return None;
+    }
+    // Titles are padded by a space on each side and separated by a divider
+    let mut x = area.x;
+    for tab in ActiveTab::ALL {
+        let end = x + tab.title().chars().count() as u16 + 2;
+        if at.x < end {
+            return Some(tab);
+        }
+        x = end + 1;
+    }
+    None
 }
 
+/// Range of the color temperature slider, in Kelvin.
+const TEMPERATURE_RANGE: (u32, u32) = (1800, 6500);
+
 struct App {
     // State
     power: bool,
     color: LightColor,
     pattern: u8,
     mic_sensitivity: u8,
+    mode: Mode,
+    sync_on_start: bool, // Cleared once the first device's session is restored
 
     // UI State
     active_tab: ActiveTab,
+    input: Option<Input>,
 
     // Color Tab Selection
-    color_selection: usize, // 0: R, 1: G, 2: B
+    color_mode: ColorMode,
+    color_selection: usize, // 0: R, 1: G, 2: B, 3: Temperature
+    temperature: u32,
+    hsv: (f64, f64, f64), // Picker position; keeps the hue while saturation is 0
+    areas: Cell<Areas>,
+    drag: Option<DragTarget>,
+
+    // Favorites and recently sent colors, both kept in the state file
+    favorites: Vec<LightColor>,
+    recent: Vec<LightColor>,
+    recent_at: Option<Instant>,
+
+    // Undo history, oldest first; changes close together merge like recent colors
+    history: Vec<TuiState>,
+    history_index: usize, // Entry the strip is showing
+    history_at: Option<Instant>,
+    history_selection: usize,
+
+    // Saved palettes, previewed on the Color tab
+    palettes: Vec<Palette>,
+    palette_selection: usize,
+    swatch_selection: usize,
+
+    // Animate Tab
+    effect_selection: usize,
+    animation_duration: Duration,
+    animation_looping: bool,
+    animation: Option<(Animation, Instant)>,
+
+    // Link to the bluetooth task
+    device: Option<DeviceConfig>,
+    link: LinkStatus,
+    rssi: Option<i16>,
+    latency: Option<Duration>,
+    log: VecDeque<LogEntry>,
+    log_scroll: usize, // lines scrolled up from the newest
+    started: Instant,
+
+    // Devices Tab
+    configured: Vec<DeviceConfig>,
+    nearby: Vec<Nearby>,
+    /// MAC of the selected row, which keeps its place as rows are re-sorted.
+    device_selection: Option<String>,
+
+    // Key bindings and how far the up/down keys move each slider
+    keymap: Keymap,
+    steps: Steps,
 
     // Communication
-    tx: mpsc::Sender<[u8; 9]>,
+    tx: mpsc::UnboundedSender<Request>,
+    feedback: mpsc::UnboundedReceiver<Feedback>,
 }
 
 impl App {
-    fn new(tx: mpsc::Sender<[u8; 9]>) -> Self {
-        Self {
+    fn new(
+        tx: mpsc::UnboundedSender<Request>,
+        feedback: mpsc::UnboundedReceiver<Feedback>,
+        config: &Config,
+        keymap: Keymap,
+        steps: Steps,
+    ) -> Self {
+        let mut errors = vec![];
+        let (favorites, recent) = State::load()
+            .map(|s| (s.favorite_colors, s.recent_colors))
+            .unwrap_or_else(|e| {
+                errors.push(e);
+                (vec![], vec![])
+            });
+
+        let mut app = Self {
             power: true,
             color: LightColor {
                 r: 255,
@@ -53,173 +298,927 @@ impl App {
             }, // Batmobile Yellow default
             pattern: 0,
             mic_sensitivity: 0,
+            mode: Mode::Color,
+            sync_on_start: config.sync_on_start,
             active_tab: ActiveTab::Color,
+            input: None,
+            color_mode: ColorMode::Rgb,
             color_selection: 0,
+            temperature: 2700,
+            hsv: (60.0, 1.0, 1.0),
+            areas: Cell::new(Areas::default()),
+            drag: None,
+            favorites,
+            recent,
+            recent_at: None,
+            history: vec![],
+            history_index: 0,
+            history_at: None,
+            history_selection: 0,
+            palettes: Palette::load_all(),
+            palette_selection: 0,
+            swatch_selection: 0,
+            effect_selection: 0,
+            animation_duration: Duration::from_secs(2),
+            animation_looping: true,
+            animation: None,
+            device: None,
+            link: LinkStatus::Disconnected,
+            rssi: None,
+            latency: None,
+            log: VecDeque::with_capacity(LOG_CAPACITY),
+            log_scroll: 0,
+            started: Instant::now(),
+            configured: config.devices(),
+            nearby: vec![],
+            device_selection: None,
+            keymap,
+            steps,
             tx,
+            feedback,
+        };
+        for e in errors {
+            app.log(e, true);
+        }
+        app.history = vec![app.snapshot()];
+        app
+    }
+
+    fn snapshot(&self) -> TuiState {
+        TuiState {
+            power: self.power,
+            color: self.color,
+            pattern: self.pattern,
+            mic: self.mic_sensitivity,
+            mode: self.mode,
+        }
+    }
+
+    /// Records the current state as an undo step, dropping any redo steps.
+    fn record(&mut self) {
+        let snapshot = self.snapshot();
+        if self.history.get(self.history_index) == Some(&snapshot) {
+            return;
+        }
+        self.history.truncate(self.history_index + 1);
+        // Only a run of the same kind of change merges, such as a drag
+        let merge = self
+            .history_at
+            .is_some_and(|at| at.elapsed() < RECENT_MERGE)
+            && self
+                .history
+                .last()
+                .is_some_and(|last| last.mode == snapshot.mode && last.power == snapshot.power);
+        if merge && self.history.len() > 1 {
+            self.history.pop();
+        }
+        self.history.push(snapshot);
+        if self.history.len() > HISTORY_CAPACITY {
+            self.history.remove(0);
+        }
+        self.history_index = self.history.len() - 1;
+        self.history_selection = self.history_index;
+        self.history_at = Some(Instant::now());
+    }
+
+    /// Sends everything the strip needs to show the current state.
+    fn send_state(&mut self) {
+        if self.power {
+            // Set the content before powering on so the old one does not flash
+            match self.mode {
+                Mode::Color => self.send_command(Controller::color(self.color)),
+                Mode::Pattern => self.send_command(Controller::pattern(self.pattern)),
+                Mode::Mic => self.send_command(Controller::mic(self.mic_sensitivity)),
+            }
+        }
+        self.send_command(Controller::power(self.power));
+    }
+
+    /// Moves to history entry `index` and sends it to the strip.
+    fn jump(&mut self, index: usize) {
+        let Some(state) = self.history.get(index).copied() else {
+            return;
+        };
+        self.animation = None;
+        self.history_index = index;
+        self.history_selection = index;
+        self.history_at = None;
+        self.power = state.power;
+        self.color = state.color;
+        self.pattern = state.pattern;
+        self.mic_sensitivity = state.mic;
+        self.mode = state.mode;
+        self.follow_color();
+        self.send_state();
+    }
+
+    fn undo(&mut self) {
+        if self.history_index > 0 {
+            self.jump(self.history_index - 1);
+        }
+    }
+
+    fn redo(&mut self) {
+        self.jump(self.history_index + 1);
+    }
+
+    fn log(&mut self, text: String, error: bool) {
+        if self.log.len() == LOG_CAPACITY {
+            self.log.pop_front();
+        }
+        self.log.push_back(LogEntry {
+            at: Instant::now(),
+            text,
+            error,
+        });
+    }
+
+    /// Applies everything the bluetooth task reported since the last frame.
+    fn drain_feedback(&mut self) {
+        while let Ok(feedback) = self.feedback.try_recv() {
+            match feedback {
+                Feedback::Status(status) => {
+                    if status != self.link {
+                        self.log(status_label(status).0.to_string(), false);
+                    }
+                    self.link = status;
+                }
+                Feedback::Device(device) => {
+                    self.rssi = None;
+                    self.latency = None;
+                    // Reconnecting to the same device keeps the session as is
+                    match &self.device {
+                        Some(current) if current.mac == device.mac => {}
+                        current => {
+                            if current.is_some()
+                                && let Err(e) = self.save_state()
+                            {
+                                self.log(e, true);
+                            }
+                            self.restore(&device.mac);
+                        }
+                    }
+                    self.device = Some(device);
+                }
+                Feedback::Rssi(rssi) => self.rssi = Some(rssi),
+                Feedback::Devices(nearby) => self.nearby = nearby,
+                Feedback::Written { payload, latency } => {
+                    self.latency = Some(latency);
+                    self.log(
+                        format!("{} ({} ms)", describe(&payload), latency.as_millis()),
+                        false,
+                    );
+                }
+                Feedback::Error(e) => self.log(e, true),
+            }
         }
     }
 
-    async fn send_command(&self, payload: [u8; 9]) {
-        if let Err(e) = self.tx.send(payload).await {
-            eprintln!("Error sending command: {}", e);
+    /// Queues a request for the bluetooth task without waiting, which
+    /// coalesces writes so holding a key never backs up the UI.
+    fn request(&mut self, request: Request) {
+        if let Err(e) = self.tx.send(request) {
+            self.log(format!("Error sending command: {e}"), true);
         }
     }
 
-    async fn toggle_power(&mut self) {
+    fn send_command(&mut self, payload: [u8; 9]) {
+        self.request(Request::Write(payload));
+    }
+
+    /// Configured devices first, then everything else the scan found.
+    fn device_rows(&self) -> Vec<DeviceRow> {
+        let rssi = |mac: &str| {
+            self.nearby
+                .iter()
+                .find(|n| n.mac == mac)
+                .and_then(|n| n.rssi)
+        };
+        let mut rows: Vec<DeviceRow> = self
+            .configured
+            .iter()
+            .map(|d| DeviceRow {
+                name: d.name.clone(),
+                mac: d.mac.clone(),
+                rssi: rssi(&d.mac),
+                configured: true,
+            })
+            .collect();
+        for nearby in &self.nearby {
+            if !self.configured.iter().any(|d| d.mac == nearby.mac) {
+                rows.push(DeviceRow {
+                    name: nearby.name.clone().unwrap_or_default(),
+                    mac: nearby.mac.clone(),
+                    rssi: nearby.rssi,
+                    configured: false,
+                });
+            }
+        }
+        rows
+    }
+
+    /// Index of the selected device in `rows`, or the first row if it is gone.
+    fn device_index(&self, rows: &[DeviceRow]) -> usize {
+        self.device_selection
+            .as_ref()
+            .and_then(|mac| rows.iter().position(|r| r.mac == *mac))
+            .unwrap_or(0)
+    }
+
+    fn toggle_power(&mut self) {
         self.power = !self.power;
-        self.send_command(Controller::power(self.power)).await;
+        // The strip goes dark, so a running animation ends on its base color
+        if let Some((animation, _)) = self.animation.take() {
+            self.color = animation.to;
+            self.follow_color();
+        }
+        self.send_command(Controller::power(self.power));
+        self.history_at = None;
+        self.record();
+        self.history_at = None;
     }
 
-    async fn set_color(&mut self) {
+    /// Moves the HSV picker to follow changes made outside it, keeping its
+    /// hue where possible.
+    fn follow_color(&mut self) {
+        let (h, s, v) = self.hsv;
+        if LightColor::from_hsv(h, s, v) != self.color {
+            let (hue, saturation, value) = self.color.to_hsv();
+            self.hsv = match (saturation > 0.0, value > 0.0) {
+                (true, _) => (hue, saturation, value),
+                (false, true) => (h, saturation, value),
+                (false, false) => (h, s, value),
+            };
+        }
+    }
+
+    /// Applies a color chosen by the user, which ends any running animation.
+    fn set_color(&mut self) {
+        self.animation = None;
+        self.send_color();
+        self.remember_color();
+        self.record();
+    }
+
+    fn send_color(&mut self) {
+        self.follow_color();
+        self.mode = Mode::Color;
         self.send_command(Controller::color(LightColor {
             r: self.color.r,
             g: self.color.g,
             b: self.color.b,
-        }))
-        .await;
+        }));
     }
 
-    async fn set_pattern(&mut self) {
-        self.send_command(Controller::pattern(self.pattern)).await;
+    fn remember_color(&mut self) {
+        if self.recent_at.is_some_and(|at| at.elapsed() < RECENT_MERGE) && !self.recent.is_empty() {
+            self.recent.remove(0);
+        }
+        self.recent.retain(|c| *c != self.color);
+        self.recent.insert(0, self.color);
+        self.recent.truncate(SWATCH_CAPACITY);
+        self.recent_at = Some(Instant::now());
     }
 
-    async fn set_mic(&mut self) {
-        self.send_command(Controller::mic(self.mic_sensitivity))
-            .await;
+    /// Applies a color picked as a whole, which gets its own recent entry.
+    fn apply_swatch(&mut self, color: Option<LightColor>) {
+        if let Some(color) = color {
+            self.color = color;
+            self.recent_at = None;
+            self.history_at = None;
+            self.set_color();
+            self.recent_at = None;
+            self.history_at = None;
+        }
     }
 
-    pub async fn on_key(&mut self, key: KeyEvent) -> bool {
-        match self.active_tab {
-            ActiveTab::Color | ActiveTab::Pattern | ActiveTab::Mic => {
-                match key.code {
-                    KeyCode::Tab | KeyCode::BackTab => {
-                        let forward = key.code == KeyCode::Tab;
-                        self.active_tab = match self.active_tab {
-                            ActiveTab::Color => {
-                                if forward {
-                                    ActiveTab::Pattern
-                                } else {
-                                    ActiveTab::Mic
-                                }
-                            }
-                            ActiveTab::Pattern => {
-                                if forward {
-                                    ActiveTab::Mic
-                                } else {
-                                    ActiveTab::Color
-                                }
-                            }
-                            ActiveTab::Mic => {
-                                if forward {
-                                    ActiveTab::Color
-                                } else {
-                                    ActiveTab::Pattern
-                                }
-                            }
-                        };
-                    }
-                    KeyCode::Char('q') => return true,
-                    KeyCode::Char('p') => self.toggle_power().await,
-
-                    // Tab specific inputs
-                    _ => match self.active_tab {
-                        ActiveTab::Color => self.handle_color_input(key.code).await,
-                        ActiveTab::Pattern => self.handle_pattern_input(key.code).await,
-                        ActiveTab::Mic => self.handle_mic_input(key.code).await,
-                    },
-                }
+    /// Adds `color` to the favorites, or removes it if it is already one,
+    /// and saves them to the state file.
+    fn toggle_favorite(&mut self, color: LightColor) {
+        if let Some(i) = self.favorites.iter().position(|c| *c == color) {
+            self.favorites.remove(i);
+        } else if self.favorites.len() < SWATCH_CAPACITY {
+            self.favorites.push(color);
+        } else {
+            self.log(
+                format!("Favorites are full ({SWATCH_CAPACITY}), remove one first"),
+                true,
+            );
+            return;
+        }
+        let saved = State::load().and_then(|mut state| {
+            state.favorite_colors = self.favorites.clone();
+            state.save()
+        });
+        if let Err(e) = saved {
+            self.log(e, true);
+        }
+    }
+
+    /// Picks up where the last session on `mac` left off, or from the state
+    /// other commands recorded, and sends it to the strip if configured to.
+    fn restore(&mut self, mac: &str) {
+        let state = match State::load() {
+            Ok(state) => state,
+            Err(e) => return self.log(e, true),
+        };
+        self.animation = None;
+        if let Some(session) = state.tui.get(mac) {
+            self.power = session.power;
+            self.color = session.color;
+            self.pattern = session.pattern;
+            self.mic_sensitivity = session.mic;
+            self.mode = session.mode;
+        } else if let Some(device) = state.devices.get(mac) {
+            self.power = device.power;
+            self.color = device.output();
+            self.pattern = device.pattern;
+            self.mic_sensitivity = device.mic;
+            self.mode = device.mode;
+        }
+        self.follow_color();
+        self.history = vec![self.snapshot()];
+        self.history_index = 0;
+        self.history_selection = 0;
+        self.history_at = None;
+
+        if std::mem::take(&mut self.sync_on_start) {
+            self.send_state();
+        }
+    }
+
+    /// Saves the session for the current device and the recent colors.
+    fn save_state(&self) -> Result<(), String> {
+        let mut state = State::load()?;
+        state.recent_colors = self.recent.clone();
+        if let Some(device) = &self.device {
+            state.tui.insert(device.mac.clone(), self.snapshot());
+            // Keep one-shot commands and modes like circadian in step
+            let mut device_state = state.device(&device.mac);
+            device_state.power = self.power;
+            device_state.mode = self.mode;
+            device_state.pattern = self.pattern;
+            device_state.mic = self.mic_sensitivity;
+            if self.mode == Mode::Color {
+                device_state = DeviceState {
+                    color: self.color,
+                    brightness: 255,
+                    ..device_state
+                };
+            }
+            state.set_device(&device.mac, device_state);
+        }
+        state.save()
+    }
+
+    fn set_hsv(&mut self, hue: f64, saturation: f64, value: f64) {
+        self.hsv = (
+            hue.clamp(0.0, 360.0),
+            saturation.clamp(0.0, 1.0),
+            value.clamp(0.0, 1.0),
+        );
+        let (h, s, v) = self.hsv;
+        self.color = LightColor::from_hsv(h, s, v);
+        self.set_color();
+    }
+
+    fn set_pattern(&mut self) {
+        self.animation = None;
+        self.mode = Mode::Pattern;
+        self.send_command(Controller::pattern(self.pattern));
+        self.record();
+    }
+
+    fn set_mic(&mut self) {
+        self.animation = None;
+        self.mode = Mode::Mic;
+        self.send_command(Controller::mic(self.mic_sensitivity));
+        self.record();
+    }
+
+    pub fn on_key(&mut self, key: KeyEvent) -> bool {
+        if self.input.is_some() {
+            self.handle_input_key(key.code);
+            return false;
+        }
+        // A key may be bound to several actions that apply on different tabs
+        for action in self.keymap.actions(&key) {
+            if action == Action::Quit {
+                return true;
+            }
+            if self.on_action(action) {
+                break;
             }
         }
         false
     }
 
-    async fn handle_color_input(&mut self, key: KeyCode) {
+    /// Performs `action`, returning false if it means nothing on this tab.
+    fn on_action(&mut self, action: Action) -> bool {
+        match action {
+            Action::NextTab => self.active_tab = self.active_tab.next(),
+            Action::PrevTab => self.active_tab = self.active_tab.prev(),
+            Action::Power => self.toggle_power(),
+            Action::Undo => self.undo(),
+            Action::Redo => self.redo(),
+            Action::LogUp => {
+                self.log_scroll = (self.log_scroll + 5).min(self.log.len().saturating_sub(1));
+            }
+            Action::LogDown => self.log_scroll = self.log_scroll.saturating_sub(5),
+            Action::EnterValue => {
+                self.input = Some(Input {
+                    text: String::new(),
+                    error: None,
+                });
+            }
+            // Tab specific inputs
+            _ => {
+                return match self.active_tab {
+                    ActiveTab::Color => self.handle_color_input(action),
+                    ActiveTab::Pattern => self.handle_pattern_input(action),
+                    ActiveTab::Mic => self.handle_mic_input(action),
+                    ActiveTab::Animate => self.handle_animate_input(action),
+                    ActiveTab::History => self.handle_history_input(action),
+                    ActiveTab::Devices => self.handle_devices_input(action),
+                };
+            }
+        }
+        true
+    }
+
+    fn handle_input_key(&mut self, key: KeyCode) {
+        let Some(input) = &mut self.input else {
+            return;
+        };
         match key {
-            KeyCode::Char('1') => self.color_selection = 0,
-            KeyCode::Char('2') => self.color_selection = 1,
-            KeyCode::Char('3') => self.color_selection = 2,
-            KeyCode::Up | KeyCode::Char('k') => {
-                match self.color_selection {
-                    0 => self.color.r = self.color.r.saturating_add(5),
-                    1 => self.color.g = self.color.g.saturating_add(5),
-                    2 => self.color.b = self.color.b.saturating_add(5),
-                    _ => {}
-                }
-                self.set_color().await;
-            }
-            KeyCode::Down | KeyCode::Char('j') => {
-                match self.color_selection {
-                    0 => self.color.r = self.color.r.saturating_sub(5),
-                    1 => self.color.g = self.color.g.saturating_sub(5),
-                    2 => self.color.b = self.color.b.saturating_sub(5),
-                    _ => {}
+            KeyCode::Esc => self.input = None,
+            KeyCode::Backspace => {
+                input.text.pop();
+                input.error = None;
+            }
+            KeyCode::Char(c) => {
+                input.text.push(c);
+                input.error = None;
+            }
+            KeyCode::Enter => {
+                let text = input.text.clone();
+                match self.apply_input(&text) {
+                    Ok(()) => self.input = None,
+                    Err(e) => {
+                        if let Some(input) = &mut self.input {
+                            input.error = Some(e);
+                        }
+                    }
                 }
-                self.set_color().await;
             }
             _ => {}
         }
     }
 
-    async fn handle_pattern_input(&mut self, key: KeyCode) {
-        match key {
-            KeyCode::Up | KeyCode::Char('k') => {
-                self.pattern = self.pattern.saturating_add(1);
-                self.set_pattern().await;
+    /// Applies typed text: a color, pattern index or mic sensitivity,
+    /// depending on the tab, or any of them with a `color`, `pattern` or `mic`
+    /// prefix.
+    fn apply_input(&mut self, text: &str) -> Result<(), String> {
+        let text = text.trim();
+        let (target, value) = match text.split_once(' ') {
+            Some((word @ ("color" | "pattern" | "mic"), rest)) => (word, rest.trim()),
+            _ => match self.active_tab {
+                ActiveTab::Pattern => ("pattern", text),
+                ActiveTab::Mic => ("mic", text),
+                _ => ("color", text),
+            },
+        };
+        match target {
+            "pattern" => {
+                self.pattern = value
+                    .parse()
+                    .ok()
+                    .filter(|index| *index <= MAX_PATTERN)
+                    .ok_or_else(|| {
+                        format!("invalid pattern '{value}', expected 0-{MAX_PATTERN}")
+                    })?;
+                self.set_pattern();
+            }
+            "mic" => {
+                self.mic_sensitivity = value
+                    .parse()
+                    .map_err(|_| format!("invalid sensitivity '{value}', expected 0-255"))?;
+                self.set_mic();
             }
-            KeyCode::Down | KeyCode::Char('j') => {
+            _ => self.apply_swatch(Some(value.parse()?)),
+        }
+        Ok(())
+    }
+
+    /// Handles tab clicks, clicks and drags on the gauges and HSV picker, and
+    /// the wheel over the pattern tab.
+    pub fn on_mouse(&mut self, mouse: MouseEvent) {
+        let areas = self.areas.get();
+        let at = Position::new(mouse.column, mouse.row);
+        match mouse.kind {
+            MouseEventKind::Down(MouseButton::Left) => {
+                if let Some(tab) = tab_at(areas.tabs, at) {
+                    self.active_tab = tab;
+                    return;
+                }
+                let swatch = |area: Rect| {
+                    area.contains(at)
+                        .then(|| ((at.x - area.x) / SWATCH_WIDTH) as usize)
+                };
+                if let Some(i) = swatch(areas.favorites) {
+                    return self.apply_swatch(self.favorites.get(i).copied());
+                }
+                if let Some(i) = swatch(areas.recent) {
+                    return self.apply_swatch(self.recent.get(i).copied());
+                }
+                self.drag = if areas.sv_field.contains(at) {
+                    Some(DragTarget::SvField)
+                } else if areas.hue_bar.contains(at) {
+                    Some(DragTarget::HueBar)
+                } else if areas.mic.contains(at) {
+                    Some(DragTarget::Mic)
+                } else {
+                    areas
+                        .channels
+                        .iter()
+                        .position(|area| area.contains(at))
+                        .map(DragTarget::Channel)
+                };
+                self.drag_to(at);
+            }
+            MouseEventKind::Drag(MouseButton::Left) => self.drag_to(at),
+            MouseEventKind::Up(_) => self.drag = None,
+            MouseEventKind::ScrollUp if areas.pattern.contains(at) => {
+                self.pattern = self.pattern.saturating_add(1).min(MAX_PATTERN);
+                self.set_pattern();
+            }
+            MouseEventKind::ScrollDown if areas.pattern.contains(at) => {
                 self.pattern = self.pattern.saturating_sub(1);
-                self.set_pattern().await;
+                self.set_pattern();
             }
             _ => {}
         }
     }
 
-    async fn handle_mic_input(&mut self, key: KeyCode) {
-        match key {
-            KeyCode::Up | KeyCode::Char('k') => {
-                self.mic_sensitivity = self.mic_sensitivity.saturating_add(1);
-                self.set_mic().await;
+    fn drag_to(&mut self, at: Position) {
+        let Some(target) = self.drag else {
+            return;
+        };
+        let areas = self.areas.get();
+        let (h, s, v) = self.hsv;
+        match target {
+            DragTarget::SvField => {
+                let (saturation, value) = fraction(areas.sv_field, at);
+                self.set_hsv(h, saturation, value);
             }
-            KeyCode::Down | KeyCode::Char('j') => {
-                self.mic_sensitivity = self.mic_sensitivity.saturating_sub(1);
-                self.set_mic().await;
+            DragTarget::HueBar => {
+                let (_, hue) = fraction(areas.hue_bar, at);
+                self.set_hsv(hue * 360.0, s, v);
             }
-            _ => {}
+            DragTarget::Mic => {
+                let (x, _) = fraction(areas.mic, at);
+                self.mic_sensitivity = (x * 255.0).round() as u8;
+                self.set_mic();
+            }
+            DragTarget::Channel(channel) => {
+                self.color_selection = channel;
+                let (x, _) = fraction(areas.channels[channel], at);
+                let level = (x * 255.0).round() as u8;
+                match channel {
+                    0 => self.color.r = level,
+                    1 => self.color.g = level,
+                    2 => self.color.b = level,
+                    _ => {
+                        let (low, high) = TEMPERATURE_RANGE;
+                        let step = self.steps.temperature.fine;
+                        let steps = (x * (high - low) as f64 / step as f64).round() as u32;
+                        self.temperature = (low + steps * step).min(high);
+                        self.color = kelvin_to_color(self.temperature);
+                    }
+                }
+                self.set_color();
+            }
+        }
+    }
+
+    fn handle_color_input(&mut self, action: Action) -> bool {
+        if self.color_mode == ColorMode::Hsv {
+            let (h, s, v) = self.hsv;
+            let Steps { hsv, hue, .. } = self.steps;
+            match action {
+                Action::Left => self.set_hsv(h, s - hsv.fine, v),
+                Action::Right => self.set_hsv(h, s + hsv.fine, v),
+                Action::Up => self.set_hsv(h, s, v + hsv.fine),
+                Action::Down => self.set_hsv(h, s, v - hsv.fine),
+                Action::UpCoarse => self.set_hsv(h, s, v + hsv.coarse),
+                Action::DownCoarse => self.set_hsv(h, s, v - hsv.coarse),
+                Action::HueDown => self.set_hsv((h - hue).rem_euclid(360.0), s, v),
+                Action::HueUp => self.set_hsv((h + hue).rem_euclid(360.0), s, v),
+                _ => return self.handle_color_common(action),
+            }
+            return true;
+        }
+        match action {
+            Action::SelectRed => self.color_selection = 0,
+            Action::SelectGreen => self.color_selection = 1,
+            Action::SelectBlue => self.color_selection = 2,
+            Action::SelectTemperature => self.color_selection = 3,
+            Action::Left => {
+                self.swatch_selection = self.swatch_selection.saturating_sub(1);
+            }
+            Action::Right => {
+                let swatches = self
+                    .palettes
+                    .get(self.palette_selection)
+                    .map_or(0, |p| p.colors.len());
+                self.swatch_selection = (self.swatch_selection + 1).min(swatches.saturating_sub(1));
+            }
+            Action::Up => self.adjust_channel(true, false),
+            Action::Down => self.adjust_channel(false, false),
+            Action::UpCoarse => self.adjust_channel(true, true),
+            Action::DownCoarse => self.adjust_channel(false, true),
+            _ => return self.handle_color_common(action),
+        }
+        true
+    }
+
+    /// Color tab actions shared by the RGB and HSV modes.
+    fn handle_color_common(&mut self, action: Action) -> bool {
+        match action {
+            Action::Favorite => self.toggle_favorite(self.color),
+            Action::ApplyFavorite(i) => self.apply_swatch(self.favorites.get(i).copied()),
+            Action::ApplyRecent(i) => self.apply_swatch(self.recent.get(i).copied()),
+            Action::ColorMode => {
+                self.color_mode = match self.color_mode {
+                    ColorMode::Rgb => ColorMode::Hsv,
+                    ColorMode::Hsv => ColorMode::Rgb,
+                };
+            }
+            Action::PrevPalette => {
+                self.palette_selection = self.palette_selection.saturating_sub(1);
+                self.swatch_selection = 0;
+            }
+            Action::NextPalette => {
+                self.palette_selection =
+                    (self.palette_selection + 1).min(self.palettes.len().saturating_sub(1));
+                self.swatch_selection = 0;
+            }
+            Action::Apply => {
+                if let Some(swatch) = self
+                    .palettes
+                    .get(self.palette_selection)
+                    .and_then(|p| p.colors.get(self.swatch_selection))
+                {
+                    self.color = *swatch;
+                    self.set_color();
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Moves the selected color channel or the temperature up or down.
+    fn adjust_channel(&mut self, up: bool, coarse: bool) {
+        let channel_step = self.steps.channel.get(coarse);
+        let channel = |v: u8| {
+            if up {
+                v.saturating_add(channel_step)
+            } else {
+                v.saturating_sub(channel_step)
+            }
+        };
+        match self.color_selection {
+            0 => self.color.r = channel(self.color.r),
+            1 => self.color.g = channel(self.color.g),
+            2 => self.color.b = channel(self.color.b),
+            _ => {
+                let step = self.steps.temperature.get(coarse);
+                self.temperature = if up {
+                    (self.temperature + step).min(TEMPERATURE_RANGE.1)
+                } else {
+                    self.temperature
+                        .saturating_sub(step)
+                        .max(TEMPERATURE_RANGE.0)
+                };
+                self.color = kelvin_to_color(self.temperature);
+            }
+        }
+        self.set_color();
+    }
+
+    /// Up/down and their coarse variants as a signed step for 0-255 values
+    /// such as the pattern index and mic sensitivity.
+    fn value_step(action: Action, step: Step<u8>) -> Option<i16> {
+        match action {
+            Action::Up => Some(step.fine as i16),
+            Action::Down => Some(-(step.fine as i16)),
+            Action::UpCoarse => Some(step.coarse as i16),
+            Action::DownCoarse => Some(-(step.coarse as i16)),
+            _ => None,
+        }
+    }
+
+    fn handle_pattern_input(&mut self, action: Action) -> bool {
+        let Some(step) = Self::value_step(action, self.steps.pattern) else {
+            return false;
+        };
+        self.pattern = (self.pattern as i16 + step).clamp(0, MAX_PATTERN as i16) as u8;
+        self.set_pattern();
+        true
+    }
+
+    fn handle_mic_input(&mut self, action: Action) -> bool {
+        let Some(step) = Self::value_step(action, self.steps.mic) else {
+            return false;
+        };
+        self.mic_sensitivity = (self.mic_sensitivity as i16 + step).clamp(0, 255) as u8;
+        self.set_mic();
+        true
+    }
+
+    fn handle_animate_input(&mut self, action: Action) -> bool {
+        let effects = Effect::value_variants();
+        match action {
+            Action::Up => {
+                self.effect_selection = self.effect_selection.saturating_sub(1);
+            }
+            Action::Down => {
+                self.effect_selection = (self.effect_selection + 1).min(effects.len() - 1);
+            }
+            Action::Left => {
+                self.animation_duration = self
+                    .animation_duration
+                    .saturating_sub(Duration::from_millis(250))
+                    .max(Duration::from_millis(250));
+            }
+            Action::Right => {
+                self.animation_duration += Duration::from_millis(250);
+            }
+            Action::ToggleLoop => self.animation_looping = !self.animation_looping,
+            Action::Apply => {
+                if self.animation.is_some() {
+                    self.stop_animation();
+                } else {
+                    let effect = effects[self.effect_selection];
+                    let animation = Animation {
+                        effect,
+                        from: LightColor::default(),
+                        to: self.color,
+                        duration: self.animation_duration,
+                        period: Duration::from_millis(100),
+                        easing: Easing::EaseInOut,
+                        looping: self.animation_looping,
+                    };
+                    self.animation = Some((animation, Instant::now()));
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_history_input(&mut self, action: Action) -> bool {
+        let last = self.history.len().saturating_sub(1);
+        match action {
+            // Newest entries are listed first
+            Action::Up => {
+                self.history_selection = (self.history_selection + 1).min(last);
+            }
+            Action::Down => {
+                self.history_selection = self.history_selection.saturating_sub(1);
+            }
+            Action::Apply => self.jump(self.history_selection),
+            Action::Favorite => {
+                if let Some(state) = self.history.get(self.history_selection).copied() {
+                    self.toggle_favorite(state.color);
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn handle_devices_input(&mut self, action: Action) -> bool {
+        let rows = self.device_rows();
+        let index = self.device_index(&rows);
+        match action {
+            Action::Up => {
+                let row = rows.get(index.saturating_sub(1));
+                self.device_selection = row.map(|r| r.mac.clone());
+            }
+            Action::Down => {
+                let row = rows.get(index + 1).or(rows.get(index));
+                self.device_selection = row.map(|r| r.mac.clone());
+            }
+            Action::Apply => {
+                let Some(row) = rows.get(index) else {
+                    return true;
+                };
+                let device = self
+                    .configured
+                    .iter()
+                    .find(|d| d.mac == row.mac)
+                    .cloned()
+                    .unwrap_or(DeviceConfig {
+                        name: if row.name.is_empty() {
+                            row.mac.clone()
+                        } else {
+                            row.name.clone()
+                        },
+                        mac: row.mac.clone(),
+                        dmx_start: None,
+                        calibration: Default::default(),
+                    });
+                self.request(Request::Connect(device));
+            }
+            Action::Disconnect => self.request(Request::Disconnect),
+            _ => return false,
+        }
+        true
+    }
+
+    /// Sends the next frame of the running animation, if any.
+    fn tick_animation(&mut self) {
+        let Some((animation, started)) = &self.animation else {
+            return;
+        };
+        let elapsed = started.elapsed();
+        let color = animation.frame(elapsed);
+        if animation.finished(elapsed) {
+            self.stop_animation();
+        } else if color != self.color {
+            // Animation frames are not worth remembering
+            self.color = color;
+            self.send_color();
+        }
+    }
+
+    /// Stops the animation and returns the strip to the color it started from.
+    fn stop_animation(&mut self) {
+        if let Some((animation, _)) = self.animation.take() {
+            self.color = animation.to;
+            self.set_color();
         }
     }
 }
 
-pub async fn run(tx: mpsc::Sender<[u8; 9]>) -> Result<(), Box<dyn Error>> {
+pub async fn run(
+    tx: mpsc::UnboundedSender<Request>,
+    feedback: mpsc::UnboundedReceiver<Feedback>,
+    config: &Config,
+) -> Result<(), Box<dyn Error>> {
+    // Check the key bindings before taking over the terminal
+    let keymap = Keymap::new(&config.keys)?;
+    let steps = config.steps.unwrap_or_default();
+    steps.validate()?;
+
     // Setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
-    execute!(stdout, EnterAlternateScreen)?;
+    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
     let backend = ratatui::backend::CrosstermBackend::new(stdout);
     let mut terminal = Terminal::new(backend)?;
 
     // Create app
-    let mut app = App::new(tx);
+    let mut app = App::new(tx, feedback, config, keymap, steps);
 
     loop {
+        app.drain_feedback();
+        app.tick_animation();
         terminal.draw(|f| ui(f, &app))?;
 
-        if event::poll(Duration::from_millis(100))?
-            && let Event::Key(key) = event::read()?
-                && key.kind == KeyEventKind::Press
-                    && app.on_key(key).await {
-                        break;
-                    }
+        let timeout = if app.animation.is_some() {
+            Duration::from_millis(1000 / ANIMATION_FPS)
+        } else {
+            Duration::from_millis(100)
+        };
+        if event::poll(timeout)? {
+            match event::read()? {
+                Event::Key(key) if key.kind == KeyEventKind::Press && app.on_key(key) => break,
+                Event::Mouse(mouse) => app.on_mouse(mouse),
+                _ => {}
+            }
+        }
     }
 
     // Restore terminal
     disable_raw_mode()?;
-    execute!(terminal.backend_mut(), LeaveAlternateScreen)?;
+    execute!(
+        terminal.backend_mut(),
+        LeaveAlternateScreen,
+        DisableMouseCapture
+    )?;
     terminal.show_cursor()?;
 
+    if let Err(e) = app.save_state() {
+        eprintln!("{e}");
+    }
     Ok(())
 }
 
 fn ui(f: &mut Frame, app: &App) {
+    app.areas.set(Areas::default());
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .margin(1)
@@ -228,7 +1227,9 @@ fn ui(f: &mut Frame, app: &App) {
                 Constraint::Length(3), // Title
                 Constraint::Length(3), // Tabs
                 Constraint::Min(0),    // Content
-                Constraint::Length(3), // Footer
+                Constraint::Length(8), // Log
+                Constraint::Length(1), // Status bar
+                Constraint::Length(4), // Footer
             ]
             .as_ref(),
         )
@@ -249,44 +1250,228 @@ fn ui(f: &mut Frame, app: &App) {
     f.render_widget(title, chunks[0]);
 
     // Tabs
-    let titles = vec!["Color", "Pattern", "Mic"];
+    let titles: Vec<&str> = ActiveTab::ALL.iter().map(|t| t.title()).collect();
     let tabs = Tabs::new(titles)
         .block(Block::default().borders(Borders::ALL).title("Modules"))
-        .select(match app.active_tab {
-            ActiveTab::Color => 0,
-            ActiveTab::Pattern => 1,
-            ActiveTab::Mic => 2,
-        })
+        .select(app.active_tab.index())
         .style(Style::default().fg(Color::White))
         .highlight_style(Style::default().fg(Color::Black).bg(Color::Yellow));
     f.render_widget(tabs, chunks[1]);
+    app.areas.set(Areas {
+        tabs: chunks[1].inner(Margin::new(1, 1)),
+        ..app.areas.get()
+    });
 
     // Content
     match app.active_tab {
         ActiveTab::Color => draw_color_tab(f, app, chunks[2]),
         ActiveTab::Pattern => draw_pattern_tab(f, app, chunks[2]),
         ActiveTab::Mic => draw_mic_tab(f, app, chunks[2]),
+        ActiveTab::Animate => draw_animate_tab(f, app, chunks[2]),
+        ActiveTab::History => draw_history_tab(f, app, chunks[2]),
+        ActiveTab::Devices => draw_devices_tab(f, app, chunks[2]),
     }
 
     // Footer
-    let footer_text = match app.active_tab {
+    let footer_text = footer(app);
+    match &app.input {
+        Some(input) => draw_input(f, input, chunks[5]),
+        None => {
+            let footer = Paragraph::new(footer_text)
+                .wrap(Wrap { trim: true })
+                .block(Block::default().borders(Borders::ALL).title("Controls"))
+                .style(Style::default().fg(Color::Gray));
+            f.render_widget(footer, chunks[5]);
+        }
+    }
+
+    draw_log(f, app, chunks[3]);
+    draw_status_bar(f, app, chunks[4]);
+}
+
+/// Help for the active tab, generated from the key bindings.
+fn footer(app: &App) -> String {
+    use Action::*;
+    let (favorites, recents) = (Action::favorites(), Action::recents());
+    let mut entries: Vec<(&[Action], &str)> = vec![
+        (&[NextTab], "Next"),
+        (&[PrevTab], "Prev"),
+        (&[Quit], "Quit"),
+    ];
+    let mut mouse = None;
+    match app.active_tab {
+        ActiveTab::Color if app.color_mode == self::ColorMode::Hsv => {
+            entries.extend([
+                (&[ColorMode][..], "RGB"),
+                (&[Left, Right], "Saturation"),
+                (&[Up, Down], "Value"),
+                (&[UpCoarse, DownCoarse], "Coarse"),
+                (&[HueDown, HueUp], "Hue"),
+                (&[Favorite], "Favorite"),
+                (&favorites, "Apply Favorite"),
+                (&recents, "Apply Recent"),
+                (&[PrevPalette, NextPalette], "Palette"),
+                (&[Apply], "Apply"),
+                (&[EnterValue], "Type"),
+            ]);
+            mouse = Some("Mouse: Pick");
+        }
         ActiveTab::Color => {
-            "Tab: Next | Shift+Tab: Prev | q: Quit | 1/2/3: Select R/G/B | ↑/↓: Adjust Value"
+            entries.extend([
+                (&[ColorMode][..], "HSV"),
+                (
+                    &[SelectRed, SelectGreen, SelectBlue, SelectTemperature],
+                    "Select R/G/B/Temp",
+                ),
+                (&[Up, Down], "Adjust Value"),
+                (&[UpCoarse, DownCoarse], "Coarse"),
+                (&[PrevPalette, NextPalette], "Palette"),
+                (&[Left, Right], "Swatch"),
+                (&[Apply], "Apply"),
+                (&[Favorite], "Favorite"),
+                (&favorites, "Apply Favorite"),
+                (&recents, "Apply Recent"),
+                (&[EnterValue], "Type"),
+            ]);
+            mouse = Some("Mouse: Adjust");
+        }
+        ActiveTab::Pattern => {
+            entries.extend([
+                (&[Up, Down][..], "Adjust Pattern Index"),
+                (&[UpCoarse, DownCoarse], "Coarse"),
+                (&[EnterValue], "Type"),
+            ]);
+            mouse = Some("Wheel: Adjust");
+        }
+        ActiveTab::Mic => {
+            entries.extend([
+                (&[Up, Down][..], "Adjust Sensitivity"),
+                (&[UpCoarse, DownCoarse], "Coarse"),
+                (&[EnterValue], "Type"),
+            ]);
+            mouse = Some("Mouse: Adjust");
         }
-        ActiveTab::Pattern => "Tab: Next | Shift+Tab: Prev | q: Quit | ↑/↓: Adjust Pattern Index",
-        ActiveTab::Mic => "Tab: Next | Shift+Tab: Prev | q: Quit | ↑/↓: Adjust Sensitivity",
+        ActiveTab::Animate => entries.extend([
+            (&[Up, Down][..], "Effect"),
+            (&[Left, Right], "Duration"),
+            (&[ToggleLoop], "Loop"),
+            (&[Apply], "Start/Stop"),
+        ]),
+        ActiveTab::History => entries.extend([
+            (&[Undo][..], "Undo"),
+            (&[Redo], "Redo"),
+            (&[Up, Down], "Select"),
+            (&[Apply], "Jump"),
+            (&[Favorite], "Favorite"),
+        ]),
+        ActiveTab::Devices => entries.extend([
+            (&[Up, Down][..], "Select"),
+            (&[Apply], "Connect"),
+            (&[Disconnect], "Disconnect"),
+        ]),
+    }
+    let mut help = app.keymap.help(&entries);
+    help.extend(mouse.map(String::from));
+    help.join(" | ")
+}
+
+fn draw_input(f: &mut Frame, input: &Input, area: Rect) {
+    let block = match &input.error {
+        Some(e) => Block::default()
+            .borders(Borders::ALL)
+            .title(e.as_str())
+            .style(Style::default().fg(Color::Red)),
+        None => Block::default()
+            .borders(Borders::ALL)
+            .title("#rrggbb, r g b, name, pattern or mic value | Enter: Apply | Esc: Cancel")
+            .style(Style::default().fg(Color::Yellow)),
     };
-    let footer = Paragraph::new(footer_text)
-        .block(Block::default().borders(Borders::ALL).title("Controls"))
-        .style(Style::default().fg(Color::Gray));
-    f.render_widget(footer, chunks[3]);
+    let text = Line::from(vec![
+        Span::raw(format!(":{}", input.text)),
+        Span::styled("█", Style::default().fg(Color::Yellow)),
+    ]);
+    f.render_widget(Paragraph::new(text).block(block), area);
+}
+
+fn draw_log(f: &mut Frame, app: &App, area: Rect) {
+    let height = area.height.saturating_sub(2) as usize;
+    let end = app.log.len().saturating_sub(app.log_scroll);
+    let start = end.saturating_sub(height);
+    let lines: Vec<Line> = app
+        .log
+        .range(start..end)
+        .map(|entry| {
+            let at = entry.at.duration_since(app.started).as_secs_f64();
+            let style = if entry.error {
+                Style::default().fg(Color::Red)
+            } else {
+                Style::default()
+            };
+            Line::from(vec![
+                Span::styled(format!("{at:>8.2}s "), Style::default().fg(Color::DarkGray)),
+                Span::styled(entry.text.clone(), style),
+            ])
+        })
+        .collect();
+    let keys = app
+        .keymap
+        .help(&[(&[Action::LogUp, Action::LogDown], "Scroll")]);
+    let title = match (keys.first(), app.log_scroll) {
+        (Some(keys), 0) => format!("Log ({keys})"),
+        (Some(keys), newer) => format!("Log ({keys}, {newer} newer)"),
+        (None, 0) => "Log".to_string(),
+        (None, newer) => format!("Log ({newer} newer)"),
+    };
+    let log = Paragraph::new(lines).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(log, area);
+}
+
+fn draw_status_bar(f: &mut Frame, app: &App, area: Rect) {
+    let (status, color) = status_label(app.link);
+    let mut spans = vec![Span::styled(
+        format!(" ● {status} "),
+        Style::default().fg(color),
+    )];
+    if let Some(device) = &app.device {
+        spans.push(Span::raw(format!("| {} ", device.name)));
+    }
+    if let Some(rssi) = app.rssi {
+        spans.push(Span::raw(format!("| RSSI {rssi} dBm ")));
+    }
+    if let Some(latency) = app.latency {
+        spans.push(Span::raw(format!(
+            "| Last write {} ms ",
+            latency.as_millis()
+        )));
+    }
+    f.render_widget(Paragraph::new(Line::from(spans)), area);
 }
 
 fn draw_color_tab(f: &mut Frame, app: &App, area: Rect) {
+    if app.color_mode == ColorMode::Hsv {
+        let chunks = Layout::default()
+            .direction(Direction::Vertical)
+            .constraints(
+                [
+                    Constraint::Length(12),
+                    Constraint::Length(3),
+                    Constraint::Min(0),
+                ]
+                .as_ref(),
+            )
+            .split(area);
+        draw_hsv_picker(f, app, chunks[0]);
+        draw_swatches(f, app, chunks[1]);
+        draw_color_preview(f, app, chunks[2]);
+        return;
+    }
+
     let chunks = Layout::default()
         .direction(Direction::Vertical)
         .constraints(
             [
+                Constraint::Length(3),
+                Constraint::Length(3),
                 Constraint::Length(3),
                 Constraint::Length(3),
                 Constraint::Length(3),
@@ -316,7 +1501,7 @@ fn draw_color_tab(f: &mut Frame, app: &App, area: Rect) {
 
     draw_gauge(
         f,
-        "Red (1)",
+        &app.keymap.title("Red", &[Action::SelectRed]),
         app.color.r,
         Color::Red,
         app.color_selection == 0,
@@ -324,7 +1509,7 @@ fn draw_color_tab(f: &mut Frame, app: &App, area: Rect) {
     );
     draw_gauge(
         f,
-        "Green (2)",
+        &app.keymap.title("Green", &[Action::SelectGreen]),
         app.color.g,
         Color::Green,
         app.color_selection == 1,
@@ -332,15 +1517,205 @@ fn draw_color_tab(f: &mut Frame, app: &App, area: Rect) {
     );
     draw_gauge(
         f,
-        "Blue (3)",
+        &app.keymap.title("Blue", &[Action::SelectBlue]),
         app.color.b,
         Color::Blue,
         app.color_selection == 2,
         chunks[2],
     );
 
-    // Preview
-    let preview_block = Block::default().borders(Borders::ALL).title("Preview");
+    let white = kelvin_to_color(app.temperature);
+    let (low, high) = TEMPERATURE_RANGE;
+    let title = app
+        .keymap
+        .title("Temperature", &[Action::SelectTemperature]);
+    let temperature = Gauge::default()
+        .block(Block::default().borders(Borders::ALL).title(title).style(
+            if app.color_selection == 3 {
+                Style::default().fg(Color::Yellow)
+            } else {
+                Style::default()
+            },
+        ))
+        .gauge_style(Style::default().fg(Color::Rgb(white.r, white.g, white.b)))
+        .ratio((app.temperature - low) as f64 / (high - low) as f64)
+        .label(format!("{}K", app.temperature));
+    f.render_widget(temperature, chunks[3]);
+
+    app.areas.set(Areas {
+        channels: [0, 1, 2, 3].map(|i| chunks[i].inner(Margin::new(1, 1))),
+        ..app.areas.get()
+    });
+
+    draw_swatches(f, app, chunks[4]);
+    draw_color_preview(f, app, chunks[5]);
+}
+
+/// Black or white, whichever stands out against `color`.
+fn contrast(color: LightColor) -> Color {
+    if color.r as u32 + color.g as u32 + color.b as u32 > 384 {
+        Color::Black
+    } else {
+        Color::White
+    }
+}
+
+/// Favorites and recent colors, each labelled with its number.
+fn draw_swatches(f: &mut Frame, app: &App, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Percentage(50), Constraint::Percentage(50)].as_ref())
+        .split(area);
+    let row = |colors: &[LightColor]| {
+        let spans: Vec<Span> = colors
+            .iter()
+            .enumerate()
+            .map(|(i, c)| {
+                Span::styled(
+                    format!("{} {}", i + 1, " ".repeat(SWATCH_WIDTH as usize - 2)),
+                    Style::default()
+                        .bg(Color::Rgb(c.r, c.g, c.b))
+                        .fg(contrast(*c)),
+                )
+            })
+            .collect();
+        Line::from(spans)
+    };
+
+    let action = if app.favorites.contains(&app.color) {
+        "Remove"
+    } else {
+        "Save"
+    };
+    let keys = app.keymap.help(&[
+        (&Action::favorites(), "Apply"),
+        (&[Action::Favorite], action),
+    ]);
+    let title = format!("Favorites ({})", keys.join(" | "));
+    let favorites = Paragraph::new(row(&app.favorites))
+        .block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(favorites, chunks[0]);
+    let keys = app.keymap.help(&[(&Action::recents(), "Apply")]);
+    let title = format!("Recent ({})", keys.join(" | "));
+    let recent =
+        Paragraph::new(row(&app.recent)).block(Block::default().borders(Borders::ALL).title(title));
+    f.render_widget(recent, chunks[1]);
+
+    let inner = |area: Rect, count: usize| Rect {
+        width: (count as u16 * SWATCH_WIDTH).min(area.width.saturating_sub(2)),
+        ..area.inner(Margin::new(1, 1))
+    };
+    app.areas.set(Areas {
+        favorites: inner(chunks[0], app.favorites.len()),
+        recent: inner(chunks[1], app.recent.len()),
+        ..app.areas.get()
+    });
+}
+
+fn draw_hsv_picker(f: &mut Frame, app: &App, area: Rect) {
+    let chunks = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints([Constraint::Min(0), Constraint::Length(8)].as_ref())
+        .split(area);
+    let (hue, saturation, value) = app.hsv;
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(
+            "HSV {:.0}° {:.0}% {:.0}%",
+            hue,
+            saturation * 100.0,
+            value * 100.0
+        ))
+        .style(Style::default().fg(Color::Yellow));
+    let field = block.inner(chunks[0]);
+    let size = (field.width as usize, field.height as usize * 2);
+    let cursor = contrast(app.color);
+    let canvas = Canvas::default()
+        .block(block)
+        .marker(Marker::HalfBlock)
+        .x_bounds([0.0, 1.0])
+        .y_bounds([0.0, 1.0])
+        .paint(move |ctx| {
+            ctx.draw(&Gradient {
+                size,
+                color: |s, v| LightColor::from_hsv(hue, s, v),
+            });
+            for (x1, y1, x2, y2) in [
+                (saturation - 0.04, value, saturation + 0.04, value),
+                (saturation, value - 0.08, saturation, value + 0.08),
+            ] {
+                ctx.draw(&CanvasLine {
+                    x1,
+                    y1,
+                    x2,
+                    y2,
+                    color: cursor,
+                });
+            }
+        });
+    f.render_widget(canvas, chunks[0]);
+
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Hue")
+        .style(Style::default().fg(Color::Yellow));
+    let bar = block.inner(chunks[1]);
+    let bar_size = (bar.width as usize, bar.height as usize * 2);
+    let canvas = Canvas::default()
+        .block(block)
+        .marker(Marker::HalfBlock)
+        .x_bounds([0.0, 1.0])
+        .y_bounds([0.0, 1.0])
+        .paint(move |ctx| {
+            ctx.draw(&Gradient {
+                size: bar_size,
+                color: |_, y| LightColor::from_hsv(y * 360.0, 1.0, 1.0),
+            });
+            let y = hue / 360.0;
+            ctx.draw(&CanvasLine {
+                x1: 0.0,
+                y1: y,
+                x2: 1.0,
+                y2: y,
+                color: contrast(LightColor::from_hsv(hue, 1.0, 1.0)),
+            });
+        });
+    f.render_widget(canvas, chunks[1]);
+
+    app.areas.set(Areas {
+        sv_field: field,
+        hue_bar: bar,
+        ..app.areas.get()
+    });
+}
+
+/// How to change the pattern, naming the keys currently bound.
+fn pattern_hint(keymap: &Keymap) -> String {
+    let mut ways = vec![];
+    if let Some(keys) = keymap.keys(&[Action::Up, Action::Down]) {
+        ways.push(format!("{keys} keys"));
+    }
+    ways.push("the mouse wheel".to_string());
+    if let Some(key) = keymap.key(Action::EnterValue) {
+        ways.push(format!("{key} and a number"));
+    }
+    let ways = match ways.split_last() {
+        Some((last, rest)) if !rest.is_empty() => format!("{}, or {last}", rest.join(", ")),
+        _ => ways.join(""),
+    };
+    format!("Use {ways} to change the pattern.")
+}
+
+fn draw_color_preview(f: &mut Frame, app: &App, area: Rect) {
+    let palette = app.palettes.get(app.palette_selection);
+    let preview_title = match palette {
+        Some(palette) => app.keymap.title(
+            &format!("Preview | Palette: {}", palette.name),
+            &[Action::PrevPalette, Action::NextPalette],
+        ),
+        None => "Preview".to_string(),
+    };
+    let preview_block = Block::default().borders(Borders::ALL).title(preview_title);
 
     let canvas = Canvas::default()
         .block(preview_block)
@@ -370,9 +1745,32 @@ fn draw_color_tab(f: &mut Frame, app: &App, area: Rect) {
             ];
 
             ctx.draw(&FilledPolygon { points, color });
+
+            // Palette swatches along the bottom edge
+            if let Some(palette) = palette {
+                let width = 160.0 / palette.colors.len().max(1) as f64;
+                for (i, swatch) in palette.colors.iter().enumerate() {
+                    let x = -80.0 + i as f64 * width;
+                    // The selected swatch stands taller than the others
+                    let top = if i == app.swatch_selection {
+                        29.0
+                    } else {
+                        25.0
+                    };
+                    ctx.draw(&FilledPolygon {
+                        points: vec![
+                            (x + 1.0, 20.0),
+                            (x + width - 1.0, 20.0),
+                            (x + width - 1.0, top),
+                            (x + 1.0, top),
+                        ],
+                        color: Color::Rgb(swatch.r, swatch.g, swatch.b),
+                    });
+                }
+            }
         });
 
-    f.render_widget(canvas, chunks[3]);
+    f.render_widget(canvas, area);
 }
 
 fn draw_pattern_tab(f: &mut Frame, app: &App, area: Rect) {
@@ -387,7 +1785,7 @@ fn draw_pattern_tab(f: &mut Frame, app: &App, area: Rect) {
             Style::default().add_modifier(Modifier::BOLD),
         )),
         Line::from(""),
-        Line::from("Use UP/DOWN keys to change the pattern."),
+        Line::from(pattern_hint(&app.keymap)),
         Line::from("Patterns are hardware defined."),
     ];
 
@@ -396,6 +1794,10 @@ fn draw_pattern_tab(f: &mut Frame, app: &App, area: Rect) {
         .alignment(ratatui::layout::Alignment::Center)
         .wrap(Wrap { trim: true });
     f.render_widget(p, area);
+    app.areas.set(Areas {
+        pattern: area,
+        ..app.areas.get()
+    });
 }
 
 fn draw_mic_tab(f: &mut Frame, app: &App, area: Rect) {
@@ -411,6 +1813,170 @@ fn draw_mic_tab(f: &mut Frame, app: &App, area: Rect) {
         .label(format!("Sensitivity: {}", app.mic_sensitivity));
 
     f.render_widget(gauge, area);
+    app.areas.set(Areas {
+        mic: area.inner(Margin::new(1, 1)),
+        ..app.areas.get()
+    });
+}
+
+fn draw_animate_tab(f: &mut Frame, app: &App, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Animation")
+        .style(Style::default().fg(Color::Yellow));
+
+    let running = app.animation.as_ref().map(|(a, _)| a.effect);
+    let mut text: Vec<Line> = Effect::value_variants()
+        .iter()
+        .enumerate()
+        .map(|(i, effect)| {
+            let marker = if running == Some(*effect) { "▶" } else { " " };
+            let style = if i == app.effect_selection {
+                Style::default().fg(Color::Black).bg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+            Line::from(Span::styled(format!("{marker} {}", effect.name()), style))
+        })
+        .collect();
+    text.push(Line::from(""));
+    text.push(Line::from(format!(
+        "Duration: {} ms | Loop: {}",
+        app.animation_duration.as_millis(),
+        if app.animation_looping { "on" } else { "off" }
+    )));
+    text.push(Line::from(
+        "Effects use the Color tab selection; fade starts from black.",
+    ));
+
+    let p = Paragraph::new(text)
+        .block(block)
+        .alignment(ratatui::layout::Alignment::Center)
+        .wrap(Wrap { trim: true });
+    f.render_widget(p, area);
+}
+
+fn draw_history_tab(f: &mut Frame, app: &App, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(format!(
+            "History ({} of {})",
+            app.history_index + 1,
+            app.history.len()
+        ))
+        .style(Style::default().fg(Color::Yellow));
+
+    // Newest first, scrolled to keep the selection visible
+    let height = area.height.saturating_sub(2) as usize;
+    let from_newest = app.history.len() - 1 - app.history_selection.min(app.history.len() - 1);
+    let skip = from_newest.saturating_sub(height.saturating_sub(1));
+    let text: Vec<Line> = app
+        .history
+        .iter()
+        .enumerate()
+        .rev()
+        .skip(skip)
+        .take(height)
+        .map(|(i, state)| {
+            let marker = if i == app.history_index { "▶" } else { " " };
+            let description = if !state.power {
+                "power off".to_string()
+            } else {
+                match state.mode {
+                    Mode::Color => format!("color {}", state.color),
+                    Mode::Pattern => format!("pattern {}", state.pattern),
+                    Mode::Mic => format!("mic {}", state.mic),
+                }
+            };
+            let style = if i == app.history_selection {
+                Style::default().fg(Color::Black).bg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+            let c = state.color;
+            Line::from(vec![
+                Span::styled(format!("{marker} {:>3}  ", i + 1), style),
+                Span::styled("  ", Style::default().bg(Color::Rgb(c.r, c.g, c.b))),
+                Span::styled(format!("  {description}"), style),
+            ])
+        })
+        .collect();
+
+    let p = Paragraph::new(text).block(block);
+    f.render_widget(p, area);
+}
+
+fn draw_devices_tab(f: &mut Frame, app: &App, area: Rect) {
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title("Devices (scanning)")
+        .style(Style::default().fg(Color::Yellow));
+
+    // Scrolled to keep the selection visible
+    let current = app.device.as_ref().map(|d| d.mac.as_str());
+    let rows = app.device_rows();
+    let selected = app.device_index(&rows);
+    let height = area.height.saturating_sub(2) as usize;
+    let skip = selected.saturating_sub(height.saturating_sub(1));
+    let mut text: Vec<Line> = rows
+        .iter()
+        .enumerate()
+        .skip(skip)
+        .take(height)
+        .map(|(i, row)| {
+            let marker = if Some(row.mac.as_str()) == current {
+                "▶"
+            } else {
+                " "
+            };
+            let rssi = row
+                .rssi
+                .map_or("   —   ".to_string(), |r| format!("{r:>4} dBm"));
+            let name = if row.configured {
+                format!("{} (configured)", row.name)
+            } else {
+                row.name.clone()
+            };
+            let style = if i == selected {
+                Style::default().fg(Color::Black).bg(Color::Yellow)
+            } else {
+                Style::default()
+            };
+            Line::from(Span::styled(
+                format!("{marker} {:<17} {rssi}  {name}", row.mac),
+                style,
+            ))
+        })
+        .collect();
+    if rows.is_empty() {
+        text.push(Line::from("No devices found yet."));
+    }
+
+    let p = Paragraph::new(text).block(block);
+    f.render_widget(p, area);
+}
+
+/// Fills the canvas pixel by pixel with `color(x, y)`, both in 0.0-1.0.
+/// `size` is the canvas resolution in pixels.
+struct Gradient<F> {
+    size: (usize, usize),
+    color: F,
+}
+
+impl<F: Fn(f64, f64) -> LightColor> Shape for Gradient<F> {
+    fn draw(&self, painter: &mut ratatui::widgets::canvas::Painter) {
+        let (width, height) = self.size;
+        let span = |n: usize| n.saturating_sub(1).max(1) as f64;
+        for px in 0..width {
+            for py in 0..height {
+                let (x, y) = (px as f64 / span(width), py as f64 / span(height));
+                if let Some((gx, gy)) = painter.get_point(x, y) {
+                    let c = (self.color)(x, y);
+                    painter.paint(gx, gy, Color::Rgb(c.r, c.g, c.b));
+                }
+            }
+        }
+    }
 }
 
 struct FilledPolygon {
@@ -478,3 +2044,60 @@ impl Shape for FilledPolygon {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn app() -> App {
+        let (tx, _) = mpsc::unbounded_channel();
+        let (_, feedback) = mpsc::unbounded_channel();
+        let keymap = Keymap::new(&BTreeMap::new()).unwrap();
+        App::new(tx, feedback, &Config::default(), keymap, Steps::default())
+    }
+
+    #[test]
+    fn rapid_edits_merge_into_one_step() {
+        let mut app = app();
+        let start = app.color;
+        for r in [10, 20, 30] {
+            app.color.r = r;
+            app.record();
+        }
+        assert_eq!(app.history.len(), 2);
+        assert_eq!(app.history[1].color.r, 30);
+
+        // Switching to a pattern is a different kind of change
+        app.mode = Mode::Pattern;
+        app.record();
+        assert_eq!(app.history.len(), 3);
+
+        app.undo();
+        assert_eq!((app.mode, app.color.r), (Mode::Color, 30));
+        app.undo();
+        assert_eq!(app.color, start);
+    }
+
+    #[test]
+    fn new_edits_clear_redo() {
+        let mut app = app();
+        for r in [10, 20] {
+            app.color.r = r;
+            app.record();
+            // As if the merge window had passed
+            app.history_at = None;
+        }
+        assert_eq!(app.history.len(), 3);
+
+        app.undo();
+        assert_eq!(app.color.r, 10);
+        app.color.g = 50;
+        app.record();
+        assert_eq!(app.history.len(), 3);
+        assert_eq!(app.history_index, 2);
+        app.redo();
+        assert_eq!((app.color.r, app.color.g), (10, 50));
+    }
+}